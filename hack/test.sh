@@ -44,10 +44,45 @@ inventory="${FCOS_HARNESS_WORK_DIR}/inventory.yml"
 sed "s/ansible_port: .*/ansible_port: ${FCOS_HARNESS_SSH_PORT}/" \
     "${root}/hack/test/inventory.kerosene.yml" > "${inventory}"
 
+cd "${root}"
+
+# Run kerosene with the given arguments, expecting it to fail with an error
+# matching a pattern
+expect_failure() {
+    local pattern="$1" output
+    shift
+    echo ">>> Running kerosene $* (expected to fail)..."
+    if output="$("${kerosene_bin}" -i "${inventory}" "$@" 2>&1 </dev/null)"; then
+        echo >&2 ">>> kerosene $* unexpectedly succeeded"
+        exit 1
+    fi
+    if ! grep -q -- "${pattern}" <<<"${output}"; then
+        echo >&2 "${output}"
+        echo >&2 ">>> kerosene $* did not fail with '${pattern}'"
+        exit 1
+    fi
+}
+
+# -- Run the failing playbooks; force_handlers is checked by the E2E playbook --
+expect_failure "running notified handlers after failure" hack/test/force_handlers.yml
+expect_failure "role 'vars_role' argument validation failed" hack/test/role_validation.yml
+expect_failure "extra vars must be a mapping" -e '["a", "b"]' hack/test/extra_vars.yml
+
+# -- Run the extra vars playbook, without a terminal for vars_prompt --
+echo ">>> Running kerosene extra vars playbook..."
+"${kerosene_bin}" -i "${inventory}" \
+    -e @hack/test/vars/extra.yml \
+    -e 'extra_kv=kv-ok extra_override=from-cli' \
+    -e '{"extra_json": {"nested": "json-ok"}}' \
+    hack/test/extra_vars.yml </dev/null
+
 # -- Run kerosene E2E test from host --
 echo ">>> Running kerosene E2E test playbook..."
-cd "${root}"
-RUST_LOG=trace "${kerosene_bin}" -i "${inventory}" hack/test/playbook.yml
+RUST_LOG=trace "${kerosene_bin}" -i "${inventory}" \
+    --vault-password-file hack/test/vault-password.txt \
+    --sops-age-key-file hack/test/sops-age-key.txt \
+    --fact-cache "${FCOS_HARNESS_WORK_DIR}/facts" --flush-cache \
+    hack/test/playbook.yml
 
 echo ">>> All E2E tests passed!"
 
@@ -1,6 +1,7 @@
 use std::{
     ffi::{OsStr, OsString},
     process::Command,
+    time::Duration,
 };
 
 use eyre::{Context, eyre};
@@ -10,11 +11,16 @@ use tracing::{Level, debug};
 /// %r = remote user, %h = host, %p = port
 const SSH_CONTROL_PATH: &str = "/tmp/kerosene-ssh-%r@%h:%p";
 
+/// How long `timeout(1)` waits after SIGTERM before sending SIGKILL.
+pub const TIMEOUT_KILL_AFTER: Duration = Duration::from_secs(5);
+
 pub struct PreparedCommand<'a> {
     pub target: &'a CommandTarget,
     pub command: OsString,
     pub args: Vec<OsString>,
     pub working_directory: Option<OsString>,
+    pub environment: Vec<(String, String)>,
+    pub timeout: Option<Duration>,
     // Flag that this command does not change system state
     pub read_only: bool,
 }
@@ -26,6 +32,8 @@ impl<'a> PreparedCommand<'a> {
             command: cmd.as_ref().into(),
             args: Default::default(),
             working_directory: Default::default(),
+            environment: Default::default(),
+            timeout: None,
             read_only: false,
         }
     }
@@ -59,40 +67,75 @@ impl<'a> PreparedCommand<'a> {
         self
     }
 
+    /// Set environment variables for the command, after any privilege
+    /// escalation.
+    pub fn envs(&mut self, environment: &[(String, String)]) -> &mut PreparedCommand<'a> {
+        self.environment.extend_from_slice(environment);
+        self
+    }
+
+    /// Terminate the command's process group with `timeout(1)` once
+    /// `timeout` has passed.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut PreparedCommand<'a> {
+        self.timeout = timeout;
+        self
+    }
+
     fn build_shell_command_string(
-        elevate: Option<&[String]>,
+        elevate: Option<&Elevate>,
+        timeout: Option<Duration>,
         working_directory: Option<&OsString>,
+        environment: &[(String, String)],
         command: &OsStr,
         args: &[OsString],
     ) -> eyre::Result<String> {
-        let mut parts: Vec<&str> = Vec::new();
+        let mut parts: Vec<String> = Vec::new();
 
-        if let Some(elevate) = elevate {
-            parts.extend(elevate.iter().map(String::as_str));
+        if let Some(timeout) = timeout {
+            parts.extend([
+                "timeout".to_owned(),
+                format!("--kill-after={}s", TIMEOUT_KILL_AFTER.as_secs()),
+                format!("{}s", timeout.as_millis().div_ceil(1000).max(1)),
+            ]);
         }
 
+        if working_directory.is_some() || !environment.is_empty() {
+            parts.push("env".to_owned());
+        }
         if let Some(chdir) = working_directory {
-            parts.extend(["env", "--chdir"]);
+            parts.push("--chdir".to_owned());
             parts.push(
                 chdir
                     .to_str()
-                    .ok_or_else(|| eyre!("working directory is not valid UTF-8"))?,
+                    .ok_or_else(|| eyre!("working directory is not valid UTF-8"))?
+                    .to_owned(),
             );
         }
+        parts.extend(
+            environment
+                .iter()
+                .map(|(name, value)| format!("{name}={value}")),
+        );
 
         parts.push(
             command
                 .to_str()
-                .ok_or_else(|| eyre!("command is not valid UTF-8"))?,
+                .ok_or_else(|| eyre!("command is not valid UTF-8"))?
+                .to_owned(),
         );
         for arg in args {
             parts.push(
                 arg.to_str()
-                    .ok_or_else(|| eyre!("argument is not valid UTF-8"))?,
+                    .ok_or_else(|| eyre!("argument is not valid UTF-8"))?
+                    .to_owned(),
             );
         }
 
-        shlex::try_join(parts.iter().copied())
+        if let Some(elevate) = elevate {
+            parts = elevate.wrap(parts)?;
+        }
+
+        shlex::try_join(parts.iter().map(String::as_str))
             .map_err(|e| eyre!("failed to shell-quote command: {e}"))
     }
 
@@ -106,8 +149,10 @@ impl<'a> PreparedCommand<'a> {
 
             CommandTarget::Local { elevate, .. } => {
                 let shell_cmd = Self::build_shell_command_string(
-                    elevate.as_deref(),
+                    elevate.as_ref(),
+                    self.timeout,
                     self.working_directory.as_ref(),
+                    &self.environment,
                     &self.command,
                     &self.args,
                 )?;
@@ -126,8 +171,10 @@ impl<'a> PreparedCommand<'a> {
                 ..
             } => {
                 let shell_cmd = Self::build_shell_command_string(
-                    elevate.as_deref(),
+                    elevate.as_ref(),
+                    self.timeout,
                     self.working_directory.as_ref(),
+                    &self.environment,
                     &self.command,
                     &self.args,
                 )?;
@@ -174,10 +221,165 @@ impl<'a> PreparedCommand<'a> {
     }
 }
 
+/// Privilege escalation method, as in Ansible's `become_method`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BecomeMethod {
+    Sudo,
+    Su,
+    Doas,
+    Run0,
+    Machinectl,
+}
+
+impl std::str::FromStr for BecomeMethod {
+    type Err = eyre::Report;
+
+    fn from_str(method: &str) -> eyre::Result<Self> {
+        match method {
+            "sudo" => Ok(Self::Sudo),
+            "su" => Ok(Self::Su),
+            "doas" => Ok(Self::Doas),
+            "run0" => Ok(Self::Run0),
+            "machinectl" => Ok(Self::Machinectl),
+            other => Err(eyre!("unsupported become_method '{other}'")),
+        }
+    }
+}
+
+impl BecomeMethod {
+    /// Whether a password can be fed through stdin. `su`, `doas`, `run0`
+    /// and `machinectl` only ask on a terminal or through polkit, and
+    /// commands do not run on a terminal.
+    pub fn reads_password(&self) -> bool {
+        matches!(self, Self::Sudo)
+    }
+
+    /// Flags used unless `become_flags` is set.
+    fn default_flags(&self) -> &'static [&'static str] {
+        match self {
+            Self::Sudo => &["-H", "-S", "-n"],
+            Self::Su => &[],
+            Self::Doas => &["-n"],
+            Self::Run0 => &["--no-ask-password"],
+            Self::Machinectl => &["--quiet", "--no-ask-password"],
+        }
+    }
+}
+
+/// Runs a command as another user.
+#[derive(Clone, Debug)]
+pub struct Elevate {
+    pub method: BecomeMethod,
+    pub user: String,
+    /// Replace the method's default flags
+    pub flags: Option<Vec<String>>,
+    /// Set when a password will be fed through stdin: the command then
+    /// prints [`Self::success_marker`] once privileges are escalated, and
+    /// `sudo` shows [`Self::prompt`].
+    pub key: Option<String>,
+}
+
+impl Elevate {
+    pub fn prompt(&self) -> Option<String> {
+        self.key
+            .as_ref()
+            .map(|key| format!("[kerosene become password, key={key}]: "))
+    }
+
+    pub fn success_marker(&self) -> Option<String> {
+        self.key
+            .as_ref()
+            .map(|key| format!("KEROSENE-BECOME-SUCCESS-{key}"))
+    }
+
+    /// Where a password prompt starts at the end of `output`, if any.
+    pub fn find_prompt(&self, output: &[u8]) -> Option<usize> {
+        let prompt = self.prompt()?;
+        output
+            .ends_with(prompt.as_bytes())
+            .then(|| output.len() - prompt.len())
+    }
+
+    /// The command line running `command` through this method.
+    fn wrap(&self, mut command: Vec<String>) -> eyre::Result<Vec<String>> {
+        if let Some(marker) = self.success_marker() {
+            let mut wrapped = vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                format!("echo {marker}; exec \"$@\""),
+                "sh".to_owned(),
+            ];
+            wrapped.append(&mut command);
+            command = wrapped;
+        }
+
+        let mut flags = self.flags.clone().unwrap_or_else(|| {
+            self.method
+                .default_flags()
+                .iter()
+                .map(|flag| (*flag).to_owned())
+                .collect()
+        });
+        let user = &self.user;
+
+        let mut argv = match self.method {
+            BecomeMethod::Sudo => {
+                if let Some(prompt) = self.prompt() {
+                    flags.retain(|flag| flag != "-n");
+                    if !flags.iter().any(|flag| flag == "-S") {
+                        flags.push("-S".to_owned());
+                    }
+                    flags.extend(["-p".to_owned(), prompt]);
+                }
+                let mut argv = vec!["sudo".to_owned()];
+                argv.append(&mut flags);
+                argv.extend(["-u".to_owned(), user.clone(), "--".to_owned()]);
+                argv
+            }
+            BecomeMethod::Su => {
+                let mut argv = vec!["su".to_owned()];
+                argv.append(&mut flags);
+                argv.extend([
+                    user.clone(),
+                    "-c".to_owned(),
+                    shlex::try_join(command.iter().map(String::as_str))
+                        .map_err(|e| eyre!("failed to shell-quote command: {e}"))?,
+                ]);
+                return Ok(argv);
+            }
+            BecomeMethod::Doas => {
+                let mut argv = vec!["doas".to_owned()];
+                argv.append(&mut flags);
+                argv.extend(["-u".to_owned(), user.clone()]);
+                argv
+            }
+            BecomeMethod::Run0 => {
+                let mut argv = vec!["run0".to_owned()];
+                argv.append(&mut flags);
+                argv.push(format!("--user={user}"));
+                argv
+            }
+            BecomeMethod::Machinectl => {
+                // The command of `machinectl shell` must be an absolute path
+                let mut argv = vec!["machinectl".to_owned()];
+                argv.append(&mut flags);
+                argv.extend([
+                    "shell".to_owned(),
+                    format!("{user}@"),
+                    "/usr/bin/env".to_owned(),
+                ]);
+                argv
+            }
+        };
+        argv.append(&mut command);
+        Ok(argv)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum CommandTarget {
     Local {
-        elevate: Option<Vec<String>>,
+        elevate: Option<Elevate>,
         dry: bool,
     },
     Remote {
@@ -186,7 +388,7 @@ pub enum CommandTarget {
         port: Option<u16>,
         ssh_key: Option<String>,
         ssh_extra_args: Vec<String>,
-        elevate: Option<Vec<String>>,
+        elevate: Option<Elevate>,
         dry: bool,
     },
 }
@@ -201,6 +403,19 @@ impl Default for CommandTarget {
 }
 
 impl CommandTarget {
+    /// Only run read-only commands, as in check mode.
+    pub fn set_dry(&mut self, value: bool) {
+        match self {
+            Self::Local { dry, .. } | Self::Remote { dry, .. } => *dry = value,
+        }
+    }
+
+    pub fn is_dry(&self) -> bool {
+        match self {
+            Self::Local { dry, .. } | Self::Remote { dry, .. } => *dry,
+        }
+    }
+
     pub async fn reset(&self) -> eyre::Result<()> {
         match self {
             Self::Local { .. } => {}
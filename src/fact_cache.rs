@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use eyre::Context;
+use serde_yaml::{Mapping, Value};
+use tracing::debug;
+
+use crate::render;
+
+/// Facts that outlive a play: gathered facts (as `ansible_facts`) and
+/// `cacheable` `set_fact` values, per host.
+///
+/// Facts are always kept for the rest of the run. With a directory, each
+/// host's facts are also written to `<dir>/<host>.json` and read back by
+/// later runs until they are older than the timeout.
+#[derive(Clone, Debug, Default)]
+pub struct FactCache {
+    dir: Option<PathBuf>,
+    /// Zero never expires.
+    timeout: Duration,
+    hosts: Arc<Mutex<HashMap<String, Mapping>>>,
+}
+
+/// Drop the `!unsafe` tags of stored facts, which JSON cannot represent.
+fn untagged(value: Value) -> Value {
+    match value {
+        Value::Tagged(tagged) => untagged(tagged.value),
+        Value::Sequence(seq) => Value::Sequence(seq.into_iter().map(untagged).collect()),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (key, untagged(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+impl FactCache {
+    pub fn new(dir: Option<PathBuf>, timeout: Duration) -> Self {
+        Self {
+            dir,
+            timeout,
+            hosts: Default::default(),
+        }
+    }
+
+    fn path(dir: &Path, host: &str) -> PathBuf {
+        dir.join(format!("{}.json", host.replace('/', "_")))
+    }
+
+    fn is_expired(&self, path: &Path) -> eyre::Result<bool> {
+        if self.timeout.is_zero() {
+            return Ok(false);
+        }
+        let modified = path.metadata()?.modified()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        Ok(age > self.timeout)
+    }
+
+    fn read(&self, host: &str) -> eyre::Result<Mapping> {
+        let Some(dir) = &self.dir else {
+            return Ok(Mapping::new());
+        };
+        let path = Self::path(dir, host);
+        if !path.exists() {
+            return Ok(Mapping::new());
+        }
+        if self.is_expired(&path)? {
+            debug!(host, path = %path.display(), "cached facts expired");
+            std::fs::remove_file(&path)?;
+            return Ok(Mapping::new());
+        }
+
+        let json = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read fact cache {}", path.display()))?;
+        let facts: Mapping = serde_json::from_str(&json)
+            .wrap_err_with(|| format!("failed to parse fact cache {}", path.display()))?;
+        Ok(facts)
+    }
+
+    fn write(&self, host: &str, facts: &Mapping) -> eyre::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("failed to create fact cache {}", dir.display()))?;
+
+        let json = serde_json::to_vec_pretty(&untagged(Value::Mapping(facts.clone())))
+            .wrap_err("failed to serialize facts")?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(&json)?;
+        file.persist(Self::path(dir, host))
+            .wrap_err("failed to write fact cache")?;
+        Ok(())
+    }
+
+    /// The cached facts of `host`, as stored. Host data is never evaluated
+    /// as a template, so every string is marked `!unsafe`.
+    pub fn get(&self, host: &str) -> eyre::Result<Mapping> {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(facts) = hosts.get(host) {
+            return Ok(facts.clone());
+        }
+
+        let facts = match render::mark_unsafe(Value::Mapping(self.read(host)?)) {
+            Value::Mapping(facts) => facts,
+            _ => unreachable!("marking a mapping unsafe keeps it a mapping"),
+        };
+        hosts.insert(host.to_owned(), facts.clone());
+        Ok(facts)
+    }
+
+    /// The facts of `host` as variables: the cached facts, plus each of
+    /// `ansible_facts` as `ansible_<name>`.
+    pub fn vars(&self, host: &str) -> eyre::Result<HashMap<String, Value>> {
+        let facts = self.get(host)?;
+        let mut vars = HashMap::new();
+        if let Some(Value::Mapping(ansible_facts)) = facts.get("ansible_facts") {
+            for (name, value) in ansible_facts {
+                if let Some(name) = name.as_str() {
+                    vars.insert(format!("ansible_{name}"), value.clone());
+                }
+            }
+        }
+        for (name, value) in facts {
+            if let Value::String(name) = name {
+                vars.insert(name, value);
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Store `facts` for `host`, replacing those of the same name.
+    pub fn update(&self, host: &str, facts: Mapping) -> eyre::Result<()> {
+        let mut cached = self.get(host)?;
+        cached.extend(facts);
+        self.write(host, &cached)?;
+        self.hosts.lock().unwrap().insert(host.to_owned(), cached);
+        Ok(())
+    }
+
+    /// Forget all facts of `host`.
+    pub fn clear(&self, host: &str) -> eyre::Result<()> {
+        self.hosts
+            .lock()
+            .unwrap()
+            .insert(host.to_owned(), Mapping::new());
+        if let Some(dir) = &self.dir {
+            let path = Self::path(dir, host);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .wrap_err_with(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,31 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
 
 use eyre::eyre;
+use kerosene::load_yaml;
 use serde::Deserialize;
 use serde_yaml::Value;
 
 /// Top-level inventory: group name → InventoryGroup
 #[derive(Debug, Deserialize)]
-pub struct Inventory(pub HashMap<String, InventoryGroup>);
+pub struct Inventory {
+    #[serde(flatten)]
+    pub groups: HashMap<String, InventoryGroup>,
+
+    /// Variables loaded from `group_vars/` next to the inventory file.
+    #[serde(skip)]
+    pub group_vars: HashMap<String, HashMap<String, Value>>,
+    /// Variables loaded from `host_vars/` next to the inventory file.
+    #[serde(skip)]
+    pub host_vars: HashMap<String, HashMap<String, Value>>,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct InventoryGroup {
     pub hosts: Option<HashMap<String, Option<HostVars>>>,
+    pub vars: Option<HashMap<String, Value>>,
 }
 
 /// Per-host connection variables (Ansible-compatible names).
@@ -26,6 +41,32 @@ pub struct HostVars {
     pub extra: HashMap<String, Value>,
 }
 
+impl HostVars {
+    /// All host variables as a flat map, connection variables included.
+    pub fn to_vars(&self) -> HashMap<String, Value> {
+        let mut vars = self.extra.clone();
+        let known = [
+            ("ansible_host", self.ansible_host.clone().map(Value::from)),
+            ("ansible_user", self.ansible_user.clone().map(Value::from)),
+            ("ansible_port", self.ansible_port.map(Value::from)),
+            (
+                "ansible_ssh_private_key_file",
+                self.ansible_ssh_private_key_file.clone().map(Value::from),
+            ),
+            (
+                "ansible_ssh_extra_args",
+                self.ansible_ssh_extra_args.clone().map(Value::from),
+            ),
+        ];
+        for (key, value) in known {
+            if let Some(value) = value {
+                vars.insert(key.to_owned(), value);
+            }
+        }
+        vars
+    }
+}
+
 /// A host resolved from inventory, ready for CommandTarget construction.
 #[derive(Debug, Clone)]
 pub struct ResolvedHost {
@@ -35,25 +76,64 @@ pub struct ResolvedHost {
     pub port: Option<u16>,
     pub ssh_key: Option<String>,
     pub ssh_extra_args: Vec<String>,
+
+    /// Groups this host is a member of, excluding `all`.
+    pub groups: Vec<String>,
+    /// Inventory variables: group vars overlaid with host vars.
+    pub vars: HashMap<String, Value>,
 }
 
 impl Inventory {
+    /// Load an inventory file along with `group_vars/` and `host_vars/`
+    /// directories located next to it.
+    pub fn load(path: &Path) -> eyre::Result<Option<Self>> {
+        let Some(mut inventory) = load_yaml::<Inventory>(path)? else {
+            return Ok(None);
+        };
+
+        let basedir = path.parent().unwrap_or(Path::new("."));
+        let mut group_names: HashSet<&String> = inventory.groups.keys().collect();
+        let all = "all".to_string();
+        group_names.insert(&all);
+
+        let mut group_vars = HashMap::new();
+        for group in group_names {
+            if let Some(vars) = load_vars_entry(&basedir.join("group_vars"), group)? {
+                group_vars.insert(group.clone(), vars);
+            }
+        }
+
+        let mut host_vars = HashMap::new();
+        for host in inventory.host_names() {
+            if let Some(vars) = load_vars_entry(&basedir.join("host_vars"), &host)? {
+                host_vars.insert(host, vars);
+            }
+        }
+
+        inventory.group_vars = group_vars;
+        inventory.host_vars = host_vars;
+        Ok(Some(inventory))
+    }
+
     /// Resolve a play's `hosts:` pattern to a list of hosts.
     /// Supports "all" (every host in every group) or a single group name.
     pub fn resolve_hosts(&self, pattern: &str) -> eyre::Result<Vec<ResolvedHost>> {
         let groups: Vec<&InventoryGroup> = if pattern == "all" {
-            self.0.values().collect()
-        } else if let Some(group) = self.0.get(pattern) {
+            self.groups.values().collect()
+        } else if let Some(group) = self.groups.get(pattern) {
             vec![group]
         } else {
             return Err(eyre!("no group matched pattern '{pattern}'"));
         };
 
+        let mut seen = HashSet::new();
         let mut resolved = Vec::new();
         for group in groups {
             if let Some(hosts) = &group.hosts {
-                for (name, vars) in hosts {
-                    resolved.push(resolve_host(name, vars.as_ref()));
+                for name in hosts.keys() {
+                    if seen.insert(name) {
+                        resolved.push(self.resolve_host(name));
+                    }
                 }
             }
         }
@@ -64,31 +144,179 @@ impl Inventory {
 
         Ok(resolved)
     }
-}
 
-fn resolve_host(name: &str, vars: Option<&HostVars>) -> ResolvedHost {
-    let (hostname, user, port, ssh_key, ssh_extra_args) = match vars {
-        Some(v) => (
-            v.ansible_host.clone().unwrap_or_else(|| name.to_owned()),
-            v.ansible_user.clone(),
-            v.ansible_port,
-            v.ansible_ssh_private_key_file.clone(),
-            v.ansible_ssh_extra_args
-                .as_deref()
+    /// Names of every host in the inventory, sorted.
+    pub fn host_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .groups
+            .values()
+            .filter_map(|group| group.hosts.as_ref())
+            .flat_map(|hosts| hosts.keys().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Group name → member host names, as exposed via the `groups` magic
+    /// variable. Always contains `all`.
+    pub fn group_members(&self) -> BTreeMap<String, Vec<String>> {
+        let mut members: BTreeMap<String, Vec<String>> = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                let mut hosts: Vec<String> = group
+                    .hosts
+                    .as_ref()
+                    .map(|hosts| hosts.keys().cloned().collect())
+                    .unwrap_or_default();
+                hosts.sort();
+                (name.clone(), hosts)
+            })
+            .collect();
+        members.insert("all".to_string(), self.host_names());
+        members
+    }
+
+    /// Inventory variables of every host, keyed by host name, as exposed via
+    /// the `hostvars` magic variable.
+    pub fn hostvars(&self) -> HashMap<String, HashMap<String, Value>> {
+        self.host_names()
+            .into_iter()
+            .map(|name| {
+                let host = self.resolve_host(&name);
+                let mut vars = host.vars;
+                vars.insert("inventory_hostname".into(), Value::from(host.name));
+                vars.insert(
+                    "group_names".into(),
+                    Value::Sequence(host.groups.into_iter().map(Value::from).collect()),
+                );
+                (name, vars)
+            })
+            .collect()
+    }
+
+    fn resolve_host(&self, name: &str) -> ResolvedHost {
+        let mut groups: Vec<&String> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| {
+                group
+                    .hosts
+                    .as_ref()
+                    .is_some_and(|hosts| hosts.contains_key(name))
+            })
+            .map(|(group_name, _)| group_name)
+            .collect();
+        groups.sort();
+
+        // Precedence (lowest to highest): `all` group vars, other group vars
+        // in name order, then host vars. Inline inventory vars are overridden
+        // by their `group_vars/` and `host_vars/` counterparts.
+        let mut vars = HashMap::new();
+        let group_order: Vec<&str> = std::iter::once("all")
+            .chain(
+                groups
+                    .iter()
+                    .map(|group| group.as_str())
+                    .filter(|group| *group != "all"),
+            )
+            .collect();
+        for &group in &group_order {
+            if let Some(group_vars) = self.groups.get(group).and_then(|g| g.vars.as_ref()) {
+                vars.extend(group_vars.clone());
+            }
+            if let Some(group_vars) = self.group_vars.get(group) {
+                vars.extend(group_vars.clone());
+            }
+        }
+
+        // A host listed in several groups gets the inline vars of each, in
+        // the same order as group vars
+        for group in &group_order {
+            let host_vars = self
+                .groups
+                .get(*group)
+                .and_then(|group| group.hosts.as_ref()?.get(name)?.as_ref());
+            if let Some(host_vars) = host_vars {
+                vars.extend(host_vars.to_vars());
+            }
+        }
+        if let Some(dir_vars) = self.host_vars.get(name) {
+            vars.extend(dir_vars.clone());
+        }
+
+        let (hostname, user, port, ssh_key, ssh_extra_args) = (
+            vars.get("ansible_host")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .unwrap_or_else(|| name.to_owned()),
+            vars.get("ansible_user")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            vars.get("ansible_port")
+                .and_then(Value::as_u64)
+                .and_then(|port| u16::try_from(port).ok()),
+            vars.get("ansible_ssh_private_key_file")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            vars.get("ansible_ssh_extra_args")
+                .and_then(Value::as_str)
                 .and_then(shlex::split)
                 .unwrap_or_default(),
-        ),
-        None => (name.to_owned(), None, None, None, Vec::new()),
-    };
-
-    ResolvedHost {
-        name: name.to_owned(),
-        hostname,
-        user,
-        port,
-        ssh_key,
-        ssh_extra_args,
+        );
+
+        ResolvedHost {
+            name: name.to_owned(),
+            hostname,
+            user,
+            port,
+            ssh_key,
+            ssh_extra_args,
+            groups: groups
+                .into_iter()
+                .filter(|group| *group != "all" && *group != "ungrouped")
+                .cloned()
+                .collect(),
+            vars,
+        }
+    }
+}
+
+/// Load `<dir>/<name>.yml`, `<dir>/<name>.yaml` or every YAML file inside
+/// `<dir>/<name>/`, merged in file name order.
+fn load_vars_entry(dir: &Path, name: &str) -> eyre::Result<Option<HashMap<String, Value>>> {
+    for extension in ["yml", "yaml"] {
+        let path = dir.join(format!("{name}.{extension}"));
+        if let Some(vars) = load_yaml::<HashMap<String, Value>>(&path)? {
+            return Ok(Some(vars));
+        }
+    }
+
+    let entry_dir = dir.join(name);
+    if !entry_dir.is_dir() {
+        return Ok(None);
+    }
+
+    let mut files: Vec<_> = std::fs::read_dir(&entry_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yml" || extension == "yaml")
+        })
+        .collect();
+    files.sort();
+
+    let mut vars = HashMap::new();
+    for file in files {
+        if let Some(file_vars) = load_yaml::<HashMap<String, Value>>(&file)? {
+            vars.extend(file_vars);
+        }
     }
+
+    Ok(Some(vars))
 }
 
 pub fn is_localhost(host: &ResolvedHost) -> bool {
@@ -1,14 +1,32 @@
-use std::{fs::File, io::ErrorKind, path::Path};
+use std::{io::ErrorKind, path::Path};
 
+use eyre::Context;
+
+pub mod sops;
+pub mod vault;
+
+/// Load and deserialize a YAML file, returning `None` if it does not exist.
+/// Files encrypted with Ansible Vault or SOPS are decrypted transparently.
 pub fn load_yaml<T>(path: &Path) -> eyre::Result<Option<T>>
 where
     T: ::serde::de::DeserializeOwned,
 {
-    let file = match File::open(path) {
-        Ok(file) => file,
+    let mut data = match std::fs::read(path) {
+        Ok(data) => data,
         Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
         Err(err) => return Err(err.into()),
     };
 
-    Ok(Some(serde_yaml::from_reader::<_, T>(file)?))
+    if vault::is_encrypted(&data) {
+        data = vault::decrypt(&data).wrap_err_with(|| format!("failed to decrypt {path:?}"))?;
+    }
+
+    if sops::is_encrypted(&data) {
+        let document = serde_yaml::from_slice(&data)?;
+        let document =
+            sops::decrypt(document).wrap_err_with(|| format!("failed to decrypt {path:?}"))?;
+        return Ok(Some(serde_yaml::from_value::<T>(document)?));
+    }
+
+    Ok(Some(serde_yaml::from_slice::<T>(&data)?))
 }
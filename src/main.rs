@@ -2,29 +2,44 @@ use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::OnceLock,
+    time::Duration,
 };
 
-use clap::Parser;
+use chrono::Local;
+use clap::{Parser, Subcommand};
 use command::CommandTarget;
-use eyre::eyre;
-use kerosene::load_yaml;
+use eyre::{Context, eyre};
+use kerosene::{load_yaml, sops, vault};
 use serde::task::HandlerDescription;
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 use tracing::{debug, info, level_filters::LevelFilter, trace, warn};
 use tracing_subscriber::EnvFilter;
 
 pub mod command;
+pub mod fact_cache;
 pub mod inventory;
 pub mod render;
 pub mod serde;
 pub mod task;
+pub mod vars;
+pub mod vault_cli;
 
-use crate::inventory::{Inventory, is_localhost};
+use crate::fact_cache::FactCache;
+use crate::inventory::{Inventory, ResolvedHost, is_localhost};
 use crate::serde::{
+    argument_spec::RoleArgumentSpecs,
+    keywords::Keywords,
     play::{Play, PlayRole},
     task::TaskDescription,
 };
-use crate::task::{KeroseneTaskInfo, TaskContext, TaskId};
+use crate::task::{
+    KeroseneTaskInfo, Password, StructuredTask, TaskContext, TaskId, async_status::AsyncJob,
+    meta::End, setup::SetupTask,
+};
+use crate::vault_cli::{VaultCommand, VaultOptions};
+
+/// Seconds between checks on an `async` task without `poll`, as in Ansible.
+const DEFAULT_POLL_INTERVAL: u64 = 15;
 
 #[derive(Debug, Default)]
 struct PlayStats {
@@ -79,13 +94,67 @@ pub fn get_task(id: &'static str) -> Option<&'static KeroseneTaskInfo> {
 }
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to inventory file
-    #[arg(long, short = 'i')]
-    inventory: PathBuf,
+    #[arg(long, short = 'i', required = true)]
+    inventory: Option<PathBuf>,
+
+    #[command(flatten)]
+    vault: VaultOptions,
+
+    /// age identity file used to decrypt SOPS-encrypted vars files
+    #[arg(long = "sops-age-key-file", env = "SOPS_AGE_KEY_FILE")]
+    sops_age_key_files: Vec<PathBuf>,
+
+    /// Set additional variables as key=value, inline YAML/JSON, or @file
+    #[arg(long = "extra-vars", short = 'e')]
+    extra_vars: Vec<String>,
+
+    /// Directory keeping gathered and cacheable facts between runs
+    #[arg(long = "fact-cache", env = "ANSIBLE_CACHE_PLUGIN_CONNECTION")]
+    fact_cache: Option<PathBuf>,
+
+    /// Seconds until cached facts expire, 0 to keep them forever
+    #[arg(
+        long = "fact-cache-timeout",
+        env = "ANSIBLE_CACHE_PLUGIN_TIMEOUT",
+        default_value_t = 86400
+    )]
+    fact_cache_timeout: u64,
+
+    /// Ask for the password used to escalate privileges
+    #[arg(long = "ask-become-pass", short = 'K')]
+    ask_become_pass: bool,
+
+    /// Seconds a task may run unless it sets `timeout`, 0 for no limit
+    #[arg(
+        long = "task-timeout",
+        env = "ANSIBLE_TASK_TIMEOUT",
+        default_value_t = 0
+    )]
+    task_timeout: u64,
+
+    /// Run notified handlers even when a task fails
+    #[arg(long = "force-handlers", env = "ANSIBLE_FORCE_HANDLERS")]
+    force_handlers: bool,
+
+    /// Clear the fact cache of every host in the inventory
+    #[arg(long = "flush-cache")]
+    flush_cache: bool,
 
     /// Path to playbook
-    play: PathBuf,
+    #[arg(required = true)]
+    play: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(subcommand)]
+    Vault(VaultCommand),
 }
 
 #[tokio::main]
@@ -101,25 +170,79 @@ async fn main() -> eyre::Result<()> {
 
     let args = Cli::parse();
 
+    if let Some(Command::Vault(command)) = args.command {
+        return command.run();
+    }
+
+    let (Some(inventory_path), Some(play_path)) = (args.inventory, args.play) else {
+        unreachable!("clap enforces inventory and playbook without a subcommand");
+    };
+
+    // Secrets must be known before any (possibly encrypted) file is read
+    vault::set_secrets(args.vault.secrets()?)?;
+    sops::set_age_key_files(args.sops_age_key_files)?;
+
     // Load plays from the playbook
-    let plays: Vec<Play> = load_yaml(&args.play)?
-        .ok_or_else(|| eyre!("playbook at '{:?}' could not be opened", &args.play))?;
+    let plays: Vec<Play> = load_yaml(&play_path)?
+        .ok_or_else(|| eyre!("playbook at '{:?}' could not be opened", &play_path))?;
 
     let current_dir = std::env::current_dir()?;
-    let play_basedir = args.play.parent().unwrap_or(&current_dir);
+    let play_basedir = play_path.parent().unwrap_or(&current_dir);
 
     let _ = known_tasks();
 
     // Load inventory
-    let inv: Inventory = load_yaml(&args.inventory)?
-        .ok_or_else(|| eyre!("inventory at '{:?}' could not be opened", &args.inventory))?;
+    let mut inv = Inventory::load(&inventory_path)?
+        .ok_or_else(|| eyre!("inventory at '{:?}' could not be opened", &inventory_path))?;
+    let playbook_dir = std::path::absolute(play_basedir).unwrap_or_else(|_| current_dir.clone());
+
+    let mut extra_vars = HashMap::new();
+    for arg in &args.extra_vars {
+        extra_vars.extend(vars::parse_extra_vars(arg)?);
+    }
+
+    let fact_cache = FactCache::new(
+        args.fact_cache,
+        Duration::from_secs(args.fact_cache_timeout),
+    );
+    let mut inventory_hostvars = inv.hostvars();
+    if args.flush_cache {
+        for host in inventory_hostvars.keys() {
+            fact_cache.clear(host)?;
+        }
+    }
+
+    let become_password = if args.ask_become_pass {
+        let password = rpassword::prompt_password("BECOME password: ")
+            .wrap_err("failed to read become password")?;
+        Some(Password::new(password))
+    } else {
+        None
+    };
+
+    let mut groups = serde_yaml::to_value(inv.group_members())?;
 
     let mut host_stats: HashMap<String, PlayStats> = HashMap::new();
+    let engine = render::Engine::default();
 
     for play in plays {
-        let hosts = inv.resolve_hosts(&play.hosts)?;
+        let mut hosts = inv.resolve_hosts(&play.hosts)?;
+        let play_host_names: Vec<String> = hosts.iter().map(|host| host.name.clone()).collect();
+        let mut hostvars = play_hostvars(&inventory_hostvars, &fact_cache)?;
+
+        // Prompt once per play, before any host is processed
+        let mut play_vars = play.vars.clone().unwrap_or_default();
+        if let Some(prompts) = &play.vars_prompt {
+            play_vars.extend(vars::prompt_vars(prompts, &extra_vars)?);
+        }
 
-        for host in &hosts {
+        for name in &play_host_names {
+            // A refreshed inventory may no longer have the host
+            let Some(host) = hosts.iter().find(|host| &host.name == name).cloned() else {
+                debug!(host = name, "host left the inventory, skipping");
+                continue;
+            };
+            let host = &host;
             info!(name = play.name(), host = host.name, "processing play");
 
             let command_target = if is_localhost(host) {
@@ -130,7 +253,8 @@ async fn main() -> eyre::Result<()> {
             } else {
                 CommandTarget::Remote {
                     hostname: host.hostname.clone(),
-                    user: host.user.clone().or(play.remote_user.clone()),
+                    // `apply_keywords` falls back to `remote_user`
+                    user: host.user.clone(),
                     port: host.port,
                     ssh_key: host.ssh_key.clone(),
                     ssh_extra_args: host.ssh_extra_args.clone(),
@@ -139,10 +263,52 @@ async fn main() -> eyre::Result<()> {
                 }
             };
 
-            let result = process_play(play_basedir, play.clone(), command_target.clone()).await;
+            let ctx = TaskContext::new(play_basedir.to_path_buf(), engine.clone());
+            {
+                let mut ctx = ctx.lock().await;
+                ctx.inventory_hostname = host.name.clone();
+                ctx.command_target = command_target.clone();
+                ctx.inventory_user = host.user.clone();
+                ctx.inventory_vars = host.vars.clone();
+                ctx.play_vars = play_vars.clone();
+                ctx.magic_vars = magic_vars(&groups, &hostvars, host, &hosts, &playbook_dir);
+                ctx.extra_vars = extra_vars.clone();
+                ctx.keywords = play.keywords.clone();
+                ctx.become_password = become_password.clone();
+                ctx.default_timeout =
+                    (args.task_timeout > 0).then(|| Duration::from_secs(args.task_timeout));
+                ctx.facts = fact_cache.vars(&host.name)?;
+                ctx.fact_cache = fact_cache.clone();
+            }
+
+            let force_handlers = play.force_handlers.unwrap_or(args.force_handlers);
+            let result =
+                process_play(ctx.clone(), play_basedir, play.clone(), force_handlers).await;
+            // Close the connection of the user set by the last keywords
+            let mut command_target = ctx.lock().await.command_target.clone();
+            command_target.set_dry(false);
             command_target.reset().await?;
             let stats = result?;
             *host_stats.entry(host.name.clone()).or_default() += stats;
+
+            let ctx = ctx.lock().await;
+            if ctx.refresh_inventory {
+                // The remaining hosts of the play see the new variables and
+                // groups, but hosts are not added to the play
+                debug!(path = ?inventory_path, "refreshing inventory");
+                inv = Inventory::load(&inventory_path)?.ok_or_else(|| {
+                    eyre!("inventory at '{:?}' could not be opened", &inventory_path)
+                })?;
+                inventory_hostvars = inv.hostvars();
+                groups = serde_yaml::to_value(inv.group_members())?;
+                hostvars = play_hostvars(&inventory_hostvars, &fact_cache)?;
+                hosts = inv.resolve_hosts(&play.hosts)?;
+                hosts.retain(|host| play_host_names.contains(&host.name));
+            }
+            if ctx.end == Some(End::Play) {
+                info!(name = play.name(), host = host.name, "play ended");
+                break;
+            }
         }
     }
 
@@ -160,40 +326,163 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+/// `hostvars` of a play: inventory variables plus the facts cached so far,
+/// including by earlier plays.
+fn play_hostvars(
+    inventory_hostvars: &HashMap<String, HashMap<String, Value>>,
+    fact_cache: &FactCache,
+) -> eyre::Result<Value> {
+    let mut hostvars = inventory_hostvars.clone();
+    for (host, vars) in &mut hostvars {
+        vars.extend(fact_cache.vars(host)?);
+    }
+    Ok(serde_yaml::to_value(hostvars)?)
+}
+
+/// Build the Ansible magic variables for `host` within a play targeting
+/// `play_hosts`. `groups` is computed once per inventory, `hostvars` once
+/// per play.
+fn magic_vars(
+    groups: &Value,
+    hostvars: &Value,
+    host: &ResolvedHost,
+    play_hosts: &[ResolvedHost],
+    playbook_dir: &Path,
+) -> HashMap<String, Value> {
+    let play_host_names = Value::Sequence(
+        play_hosts
+            .iter()
+            .map(|host| Value::from(host.name.clone()))
+            .collect(),
+    );
+
+    HashMap::from([
+        ("inventory_hostname".into(), Value::from(host.name.clone())),
+        (
+            "inventory_hostname_short".into(),
+            Value::from(host.name.split('.').next().unwrap_or(&host.name)),
+        ),
+        (
+            "group_names".into(),
+            serde_yaml::to_value(&host.groups).unwrap_or_default(),
+        ),
+        ("groups".into(), groups.clone()),
+        ("hostvars".into(), hostvars.clone()),
+        ("play_hosts".into(), play_host_names.clone()),
+        ("ansible_play_hosts".into(), play_host_names.clone()),
+        ("ansible_play_batch".into(), play_host_names),
+        (
+            "playbook_dir".into(),
+            Value::from(playbook_dir.to_string_lossy().into_owned()),
+        ),
+    ])
+}
+
 async fn process_play(
+    ctx: TaskContext,
+    basedir: &Path,
+    play: Play,
+    force_handlers: bool,
+) -> eyre::Result<PlayStats> {
+    let result = process_play_sections(ctx.clone(), basedir, play).await;
+
+    // Handlers notified before a failure still run, so that e.g. a changed
+    // unit file still gets its daemon-reload
+    if result.is_err() && force_handlers && !host_ended(&ctx).await {
+        info!("running notified handlers after failure (force_handlers)");
+        if let Err(err) = run_handlers(ctx).await {
+            warn!(?err, "handler failed after task failure");
+        }
+    }
+
+    result
+}
+
+async fn process_play_sections(
+    ctx: TaskContext,
     basedir: &Path,
     play: Play,
-    command_target: CommandTarget,
 ) -> eyre::Result<PlayStats> {
-    let ctx: TaskContext = TaskContext::new(basedir.to_path_buf());
-    ctx.lock().await.command_target = command_target;
     let mut stats = PlayStats::default();
 
+    // Role handlers are declared before those of the play, so that a play
+    // handler wins over a role handler of the same name
+    for role in play.roles.iter().flatten() {
+        let role_basedir = basedir.join("roles").join(role.name());
+        let handlers: Option<Vec<HandlerDescription>> =
+            load_yaml(&role_basedir.join("handlers/main.yml"))?;
+        if let Some(handlers) = handlers {
+            register_handlers(ctx.clone(), handlers, Some(role), Some(role_basedir)).await?;
+        }
+    }
+    if let Some(handlers) = play.handlers {
+        register_handlers(ctx.clone(), handlers, None, None).await?;
+    }
+
+    // Load vars_files, which may reference variables defined so far
+    if let Some(vars_files) = &play.vars_files {
+        let mut ctx = ctx.lock().await;
+        let loaded = vars::load_vars_files(basedir, vars_files, &ctx.vars())?;
+        ctx.play_vars.extend(loaded);
+    }
+
+    check_collections(&play.keywords);
+
+    if play.gather_facts {
+        info!(name = "Gathering Facts", "running task");
+        {
+            let mut ctx = ctx.lock().await;
+            let keywords = ctx.keywords.clone();
+            ctx.apply_keywords(&keywords)?;
+        }
+        let setup = SetupTask {
+            gather_subset: play.gather_subset.clone(),
+            ..Default::default()
+        };
+        setup.run_structured(ctx.clone()).await?;
+        stats.ok += 1;
+    }
+
     // Process pre_tasks
     if let Some(pre_tasks) = play.pre_tasks {
         stats += process_tasks(ctx.clone(), pre_tasks, None, true).await?;
     }
+    if host_ended(&ctx).await {
+        return Ok(stats);
+    }
 
     // Process roles
     if let Some(roles) = play.roles {
         for role in roles {
             let role_basedir = basedir.join("roles").join(role.name());
-            ctx.lock()
-                .await
-                .resource_dirs
-                .push_front(role_basedir.clone());
+            let role_keywords = role.keywords();
+            check_collections(&role_keywords);
+            let play_keywords = {
+                let mut ctx = ctx.lock().await;
+                ctx.resource_dirs.push_front(role_basedir.clone());
+                let inherited = role_keywords.inherit(&ctx.keywords);
+                std::mem::replace(&mut ctx.keywords, inherited)
+            };
 
             let result = process_role(ctx.clone(), &role_basedir, role).await;
 
             // Always clean up role-scoped state, even on error
             {
                 let mut ctx_inner = ctx.lock().await;
+                ctx_inner.keywords = play_keywords;
                 ctx_inner.resource_dirs.pop_front();
                 ctx_inner.role_defaults.clear();
+                ctx_inner.role_vars.clear();
                 ctx_inner.role_play_vars.clear();
+                if ctx_inner.end == Some(End::Role) {
+                    ctx_inner.end = None;
+                }
             }
 
             stats += result?;
+            if host_ended(&ctx).await {
+                return Ok(stats);
+            }
         }
     }
 
@@ -201,6 +490,9 @@ async fn process_play(
     if let Some(tasks) = play.tasks {
         stats += process_tasks(ctx.clone(), tasks, None, false).await?;
     }
+    if host_ended(&ctx).await {
+        return Ok(stats);
+    }
 
     // Process role & tasks handlers
     run_handlers(ctx.clone()).await?;
@@ -213,6 +505,23 @@ async fn process_play(
     Ok(stats)
 }
 
+/// Warn about `collections` that provide no modules. Every module is known
+/// by its short name, so the keyword does not change how tasks resolve.
+fn check_collections(keywords: &Keywords) {
+    for collection in keywords.collections.iter().flatten() {
+        let prefix = format!("{collection}.");
+        if !known_tasks().keys().any(|id| id.starts_with(&prefix)) {
+            warn!(collection, "collection provides no modules");
+        }
+    }
+}
+
+/// Whether `meta` ended the play for the current host. Pending handlers are
+/// not run then.
+async fn host_ended(ctx: &TaskContext) -> bool {
+    matches!(ctx.lock().await.end, Some(End::Host | End::Play))
+}
+
 async fn register_handlers(
     ctx: TaskContext,
     handlers: Vec<HandlerDescription>,
@@ -220,33 +529,30 @@ async fn register_handlers(
     role_resource_dir: Option<PathBuf>,
 ) -> eyre::Result<()> {
     let mut ctx = ctx.lock().await;
+    // Role handlers inherit from their role entry, then from the play
+    let keywords = match role {
+        Some(role) => role.keywords().inherit(&ctx.keywords),
+        None => ctx.keywords.clone(),
+    };
 
     for mut handler in handlers {
         handler.role_resource_dir = role_resource_dir.clone();
-        let task_id = handler.task_id.name();
-
-        let mut handler_names = HashSet::new();
+        handler.keywords = handler.keywords.inherit(&keywords);
         if let Some(name) = &handler.name {
             if let Some(role) = role {
-                handler_names.insert(format!("{} : {}", role.name(), name));
+                handler.names.push(format!("{} : {}", role.name(), name));
             }
-            handler_names.insert(name.clone());
-        }
-
-        if let Some(listen) = &handler.listen {
-            handler_names.insert(listen.clone());
-        }
-
-        for name in &handler_names {
-            ctx.known_handlers.insert(name.clone(), handler.clone());
+            handler.names.push(name.clone());
         }
 
         debug!(
             role = role.map(PlayRole::name),
-            names = ?handler_names,
-            task_id,
+            names = ?handler.names,
+            listen = ?handler.listen,
+            task_id = handler.task_id.name(),
             "registered handler"
         );
+        ctx.known_handlers.push(handler);
     }
 
     Ok(())
@@ -266,22 +572,47 @@ async fn process_role(
         ctx.lock().await.role_defaults = defaults;
     }
 
+    // Load role vars into scoped role_vars
+    let vars: Option<HashMap<String, Value>> = load_yaml(&role_basedir.join("vars/main.yml"))?;
+    if let Some(vars) = vars {
+        ctx.lock().await.role_vars = vars;
+    }
+
     // Inject play-level role vars into scoped role_play_vars
     if let Some(vars) = role.vars() {
         ctx.lock().await.role_play_vars = vars.clone();
     }
 
-    // Load role handlers
-    let handlers: Option<Vec<HandlerDescription>> =
-        load_yaml(&role_basedir.join("handlers/main.yml"))?;
-    if let Some(handlers) = handlers {
-        register_handlers(
-            ctx.clone(),
-            handlers,
-            Some(&role),
-            Some(role_basedir.to_path_buf()),
-        )
-        .await?;
+    // Validate role inputs before running anything from the role
+    let specs: Option<RoleArgumentSpecs> =
+        load_yaml(&role_basedir.join("meta/argument_specs.yml"))?;
+    if let Some(spec) = specs.and_then(|mut specs| specs.argument_specs.remove("main")) {
+        let mut ctx = ctx.lock().await;
+
+        // Spec defaults fill in for options the role defaults leave undefined
+        for (name, option) in &spec.options {
+            if let Some(default) = &option.default {
+                ctx.role_defaults
+                    .entry(name.clone())
+                    .or_insert_with(|| default.clone());
+            }
+        }
+
+        let vars = ctx.vars();
+        let mut resolved_vars = HashMap::new();
+        for name in spec.options.keys() {
+            if let Some(value) = vars.get(name)? {
+                resolved_vars.insert(name.clone(), value);
+            }
+        }
+        let errors = spec.validate(&resolved_vars);
+        if !errors.is_empty() {
+            return Err(eyre!(
+                "role '{}' argument validation failed:\n  - {}",
+                role.name(),
+                errors.join("\n  - ")
+            ));
+        }
     }
 
     // Load role tasks
@@ -317,11 +648,14 @@ async fn process_tasks(
             info!(name, task_id, "running task");
         }
         let task_info = get_task(task_id).unwrap();
-        let ignore_errors = task.ignore_errors;
-        ctx.lock().await.do_become_user = if task.r#become {
-            Some(task.become_user.unwrap_or("root".to_string()))
-        } else {
-            None
+        let keywords = task.keywords.inherit(&ctx.lock().await.keywords);
+        let async_job = match task.r#async {
+            Some(seconds) => Some(AsyncJob {
+                id: task::random_key()?,
+                timeout: Duration::from_secs(seconds),
+                poll: Duration::from_secs(task.poll.unwrap_or(DEFAULT_POLL_INTERVAL)),
+            }),
+            None => None,
         };
 
         let prev_command_target: Option<CommandTarget> = if let Some(delegate_to) = task.delegate_to
@@ -343,12 +677,41 @@ async fn process_tasks(
             None
         };
 
-        ctx.lock().await.task_vars = task.vars.unwrap_or_default();
+        {
+            let mut ctx = ctx.lock().await;
+            ctx.task_vars = task.vars.unwrap_or_default();
+            ctx.apply_keywords(&keywords)?;
+            ctx.async_job = async_job.clone();
+        }
 
-        let resolved_vars = render::resolve_vars(&ctx.lock().await.merged_vars())?;
-        let rendered_args = render::render_value(&task.args, &resolved_vars)?;
+        let vars = ctx.lock().await.vars();
+        let ignore_errors = match &keywords.ignore_errors {
+            Some(value) => value.resolve(&vars)?,
+            None => false,
+        };
+        let rendered_args = vars.render_value(&task.args)?;
+
+        let start = Local::now();
+        let mut outcome = (task_info.run)(ctx.clone(), rendered_args).await;
+        if let (Ok(result), Some(job)) = (&mut outcome, &async_job) {
+            let mut output = match result.output.take() {
+                Some(Value::Mapping(output)) => output,
+                _ => Mapping::new(),
+            };
+            output.extend(job.result(!job.poll.is_zero()));
+            result.output = Some(Value::Mapping(output));
+        }
+        if let Some(register) = &task.register {
+            let value = task::registered_result(&outcome, start, Local::now());
+            debug!(register, "registering result");
+            // Host output must never be evaluated as a template
+            ctx.lock()
+                .await
+                .facts
+                .insert(register.clone(), render::mark_unsafe(value));
+        }
 
-        match (task_info.run)(ctx.clone(), rendered_args).await {
+        match outcome {
             Ok(result) => {
                 if result.changed {
                     stats.changed += 1;
@@ -358,20 +721,10 @@ async fn process_tasks(
                     info!(name, "ok");
                 }
 
-                if let (Some(register), Some(mut value)) = (&task.register, result.output) {
-                    // Inject `changed` key into registered output mapping
-                    if let Value::Mapping(ref mut map) = value {
-                        map.insert(Value::String("changed".into()), Value::Bool(result.changed));
-                    }
-                    debug!(register, "registering output");
-                    ctx.lock().await.facts.insert(register.clone(), value);
-                }
-
                 if result.changed {
                     for notify in task.notify {
-                        let rendered_notify = render::render_str(&notify, &resolved_vars)?;
-                        let mut ctx = ctx.lock().await;
-                        ctx.pending_handlers.push_back(rendered_notify);
+                        let rendered_notify = vars.render_str(&notify)?;
+                        ctx.lock().await.notify(&rendered_notify)?;
                     }
                 }
             }
@@ -382,6 +735,7 @@ async fn process_tasks(
                 } else {
                     // Clean up before returning
                     ctx.lock().await.task_vars.clear();
+                    ctx.lock().await.async_job = None;
                     if let Some(command_target) = prev_command_target {
                         ctx.lock().await.command_target = command_target;
                     }
@@ -391,11 +745,16 @@ async fn process_tasks(
         }
 
         ctx.lock().await.task_vars.clear();
+        ctx.lock().await.async_job = None;
 
         if let Some(command_target) = prev_command_target {
             let mut ctx_inner = ctx.lock().await;
             ctx_inner.command_target = command_target;
         }
+
+        if ctx.lock().await.end.is_some() {
+            return Ok(stats);
+        }
     }
 
     if flush_handlers {
@@ -405,70 +764,78 @@ async fn process_tasks(
     Ok(stats)
 }
 
+/// Run notified handlers in declaration order, including those notified by
+/// other handlers meanwhile. A handler runs at most once per flush, which
+/// also ends notification cycles.
 pub async fn run_handlers(context: TaskContext) -> eyre::Result<()> {
-    // HACK: There's more elegant solution than this, but I don't want to
-    //       spend too much time here to design this architecture here to
-    //       avoid a deadlock. Just clone the pending handlers collection
-    //       instead.
-    let mut pending_handlers = {
-        let ctx = context.lock().await;
-        ctx.pending_handlers.clone()
-    };
+    let mut ran = HashSet::new();
 
-    if !pending_handlers.is_empty() {
-        debug!("running pending handlers");
+    loop {
+        let Some(index) = context.lock().await.pending_handlers.pop_first() else {
+            break;
+        };
+        let handler = context.lock().await.known_handlers[index].clone();
+        let handler_name = handler
+            .name
+            .clone()
+            .or_else(|| handler.listen.first().cloned())
+            .unwrap_or_default();
+        if !ran.insert(index) {
+            debug!(handler_name, "handler already ran in this flush");
+            continue;
+        }
 
-        while let Some(handler_name) = pending_handlers.pop_front() {
-            let (run, args, become_user, role_resource_dir, handler_vars) = {
-                let ctx = context.lock().await;
-                let handler = ctx
-                    .known_handlers
-                    .get(handler_name.as_str())
-                    .ok_or_else(|| eyre!("Handler '{}' is not declared", handler_name))?;
+        // Push handler's role resource dir so it can resolve role-local files
+        let applied = {
+            let mut ctx = context.lock().await;
+            if let Some(dir) = &handler.role_resource_dir {
+                ctx.resource_dirs.push_front(dir.clone());
+            }
+            ctx.task_vars = handler.vars.clone().unwrap_or_default();
+            ctx.apply_keywords(&handler.keywords)
+        };
 
-                let become_user = if handler.r#become {
-                    Some(handler.become_user.clone().unwrap_or("root".to_string()))
-                } else {
-                    None
-                };
-
-                let task = get_task(handler.task_id.name()).unwrap();
-                (
-                    task.run,
-                    handler.args.clone(),
-                    become_user,
-                    handler.role_resource_dir.clone(),
-                    handler.vars.clone().unwrap_or_default(),
-                )
-            };
+        let result = match applied {
+            Ok(()) => run_handler(context.clone(), &handler, &handler_name).await,
+            Err(err) => Err(err),
+        };
 
-            // Push handler's role resource dir so it can resolve role-local files
-            if let Some(ref dir) = role_resource_dir {
-                context.lock().await.resource_dirs.push_front(dir.clone());
+        // Always clean up resource dir and task vars, even on error
+        {
+            let mut ctx = context.lock().await;
+            ctx.task_vars.clear();
+            if handler.role_resource_dir.is_some() {
+                ctx.resource_dirs.pop_front();
             }
+        }
 
-            info!(handler_name, "running handler");
-            {
-                let mut ctx = context.lock().await;
-                ctx.do_become_user = become_user;
-                ctx.task_vars = handler_vars;
-            }
-            let result = (run)(context.clone(), args).await;
+        result?;
+    }
 
-            // Always clean up resource dir and task vars, even on error
-            {
-                let mut ctx = context.lock().await;
-                ctx.task_vars.clear();
-                if role_resource_dir.is_some() {
-                    ctx.resource_dirs.pop_front();
-                }
-            }
+    Ok(())
+}
 
-            let _ = result?;
-        }
+async fn run_handler(
+    context: TaskContext,
+    handler: &HandlerDescription,
+    handler_name: &str,
+) -> eyre::Result<()> {
+    info!(handler_name, "running handler");
+    let vars = context.lock().await.vars();
+    if !vars.evaluate_when(&handler.when)? {
+        info!(handler_name, "skipped");
+        return Ok(());
+    }
+
+    let task = get_task(handler.task_id.name()).unwrap();
+    let args = vars.render_value(&handler.args)?;
+    let output = (task.run)(context.clone(), args).await?;
 
-        let mut ctx = context.lock().await;
-        ctx.pending_handlers.clear();
+    if output.changed {
+        for notify in &handler.notify {
+            let rendered_notify = vars.render_str(notify)?;
+            context.lock().await.notify(&rendered_notify)?;
+        }
     }
 
     Ok(())
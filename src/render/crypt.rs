@@ -0,0 +1,175 @@
+//! SHA-crypt (`$5$` / `$6$`) password hashing as produced by glibc's
+//! `crypt(3)`, used by the `password_hash` filter.
+
+use sha2::{Digest, Sha256, Sha512};
+
+const ALPHABET: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const DEFAULT_ROUNDS: u32 = 5000;
+const MAX_SALT_LENGTH: usize = 16;
+
+/// Byte triples of the final SHA-256 digest, in output order.
+const SHA256_ORDER: &[(usize, usize, usize)] = &[
+    (0, 10, 20),
+    (21, 1, 11),
+    (12, 22, 2),
+    (3, 13, 23),
+    (24, 4, 14),
+    (15, 25, 5),
+    (6, 16, 26),
+    (27, 7, 17),
+    (18, 28, 8),
+    (9, 19, 29),
+];
+
+/// Byte triples of the final SHA-512 digest, in output order.
+const SHA512_ORDER: &[(usize, usize, usize)] = &[
+    (0, 21, 42),
+    (22, 43, 1),
+    (44, 2, 23),
+    (3, 24, 45),
+    (25, 46, 4),
+    (47, 5, 26),
+    (6, 27, 48),
+    (28, 49, 7),
+    (50, 8, 29),
+    (9, 30, 51),
+    (31, 52, 10),
+    (53, 11, 32),
+    (12, 33, 54),
+    (34, 55, 13),
+    (56, 14, 35),
+    (15, 36, 57),
+    (37, 58, 16),
+    (59, 17, 38),
+    (18, 39, 60),
+    (40, 61, 19),
+    (62, 20, 41),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Scheme {
+    Sha256,
+    Sha512,
+}
+
+/// Generate a random salt of the maximum length from the crypt alphabet.
+pub fn random_salt() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; MAX_SALT_LENGTH];
+    getrandom::fill(&mut bytes)?;
+    Ok(bytes
+        .iter()
+        .map(|byte| ALPHABET[usize::from(byte % 64)] as char)
+        .collect())
+}
+
+/// Hash `password` with `salt` (truncated to 16 characters). `rounds` is
+/// clamped to the range crypt accepts; when `None`, the default of 5000 is
+/// used and omitted from the output.
+pub fn hash(scheme: Scheme, password: &[u8], salt: &str, rounds: Option<u32>) -> String {
+    let salt = &salt.as_bytes()[..salt.len().min(MAX_SALT_LENGTH)];
+    let effective_rounds = rounds.map_or(DEFAULT_ROUNDS, |rounds| rounds.clamp(1000, 999_999_999));
+
+    let (id, encoded) = match scheme {
+        Scheme::Sha256 => (
+            "5",
+            encode(
+                &digest::<Sha256>(password, salt, effective_rounds),
+                SHA256_ORDER,
+            ),
+        ),
+        Scheme::Sha512 => (
+            "6",
+            encode(
+                &digest::<Sha512>(password, salt, effective_rounds),
+                SHA512_ORDER,
+            ),
+        ),
+    };
+
+    let salt = String::from_utf8_lossy(salt);
+    match rounds {
+        Some(_) => format!("${id}$rounds={effective_rounds}${salt}${encoded}"),
+        None => format!("${id}${salt}${encoded}"),
+    }
+}
+
+fn digest<D: Digest>(password: &[u8], salt: &[u8], rounds: u32) -> Vec<u8> {
+    let alternate = D::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut a = D::new().chain_update(password).chain_update(salt);
+    a.update(repeat_to(&alternate, password.len()));
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            a.update(&alternate);
+        } else {
+            a.update(password);
+        }
+        length >>= 1;
+    }
+    let mut current = a.finalize().to_vec();
+
+    let mut p = D::new();
+    for _ in 0..password.len() {
+        p.update(password);
+    }
+    let p = repeat_to(&p.finalize(), password.len());
+
+    let mut s = D::new();
+    for _ in 0..16 + usize::from(current[0]) {
+        s.update(salt);
+    }
+    let s = repeat_to(&s.finalize(), salt.len());
+
+    for round in 0..rounds {
+        let mut c = D::new();
+        if round % 2 == 1 {
+            c.update(&p);
+        } else {
+            c.update(&current);
+        }
+        if round % 3 != 0 {
+            c.update(&s);
+        }
+        if round % 7 != 0 {
+            c.update(&p);
+        }
+        if round % 2 == 1 {
+            c.update(&current);
+        } else {
+            c.update(&p);
+        }
+        current = c.finalize().to_vec();
+    }
+
+    current
+}
+
+/// Repeat `block` until it is `length` bytes long.
+fn repeat_to(block: &[u8], length: usize) -> Vec<u8> {
+    block.iter().copied().cycle().take(length).collect()
+}
+
+fn encode(digest: &[u8], order: &[(usize, usize, usize)]) -> String {
+    let mut out = String::new();
+    for &(a, b, c) in order {
+        push_24bit(&mut out, digest[a], digest[b], digest[c], 4);
+    }
+    match digest.len() {
+        32 => push_24bit(&mut out, 0, digest[31], digest[30], 3),
+        _ => push_24bit(&mut out, 0, 0, digest[63], 2),
+    }
+    out
+}
+
+fn push_24bit(out: &mut String, b2: u8, b1: u8, b0: u8, chars: usize) {
+    let mut word = (u32::from(b2) << 16) | (u32::from(b1) << 8) | u32::from(b0);
+    for _ in 0..chars {
+        out.push(ALPHABET[(word & 0x3f) as usize] as char);
+        word >>= 6;
+    }
+}
@@ -0,0 +1,749 @@
+//! Ansible's built-in filters on top of MiniJinja's own set.
+//!
+//! Filters follow the Ansible signatures (positional arguments and keyword
+//! arguments) so existing playbooks render unchanged. Values are converted
+//! through `serde_yaml::Value` where structural manipulation is needed.
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use md5::Md5;
+use minijinja::{
+    Environment, Error, ErrorKind,
+    value::{Kwargs, Rest, Value, ValueKind},
+};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+use super::{crypt, ipaddr};
+
+pub(super) fn register(env: &mut Environment<'_>) {
+    // Serialization
+    env.add_filter("to_json", to_json);
+    env.add_filter("to_nice_json", to_nice_json);
+    env.add_filter("from_json", from_json);
+    env.add_filter("to_yaml", to_yaml);
+    env.add_filter("to_nice_yaml", to_yaml);
+    env.add_filter("from_yaml", from_yaml);
+    env.add_filter("from_yaml_all", from_yaml_all);
+
+    // Encoding and hashing
+    env.add_filter("b64encode", b64encode);
+    env.add_filter("b64decode", b64decode);
+    env.add_filter("hash", hash);
+    env.add_filter("checksum", checksum);
+    env.add_filter("md5", md5);
+    env.add_filter("sha1", checksum);
+    env.add_filter("password_hash", password_hash);
+    env.add_filter("quote", quote);
+
+    // Regular expressions
+    env.add_filter("regex_replace", regex_replace);
+    env.add_filter("regex_search", regex_search);
+    env.add_filter("regex_findall", regex_findall);
+    env.add_filter("regex_escape", regex_escape);
+
+    // Dictionaries and lists
+    env.add_filter("combine", combine);
+    env.add_filter("dict2items", dict2items);
+    env.add_filter("items2dict", items2dict);
+    env.add_filter("flatten", flatten);
+    env.add_filter("union", union);
+    env.add_filter("intersect", intersect);
+    env.add_filter("difference", difference);
+    env.add_filter("symmetric_difference", symmetric_difference);
+    env.add_filter("extract", extract);
+
+    // Values
+    env.add_filter("mandatory", mandatory);
+    env.add_filter("ternary", ternary);
+    env.add_filter("bool", to_bool);
+    env.add_filter("type_debug", type_debug);
+
+    // Paths
+    env.add_filter("basename", basename);
+    env.add_filter("dirname", dirname);
+    env.add_filter("expanduser", expanduser);
+    env.add_filter("splitext", splitext);
+
+    // Networking
+    env.add_filter("ipaddr", ipaddr::ipaddr);
+    env.add_filter("ipv4", ipaddr::ipv4);
+    env.add_filter("ipv6", ipaddr::ipv6);
+    env.add_filter("ansible.utils.ipaddr", ipaddr::ipaddr);
+    env.add_filter("ansible.utils.ipv4", ipaddr::ipv4);
+    env.add_filter("ansible.utils.ipv6", ipaddr::ipv6);
+}
+
+pub(super) fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidOperation, message.into())
+}
+
+/// Convert a template value into YAML for structural manipulation.
+pub(super) fn to_yaml_value(value: &Value) -> Result<serde_yaml::Value, Error> {
+    serde_yaml::to_value(value).map_err(|err| invalid(format!("cannot convert value: {err}")))
+}
+
+fn to_json(value: Value, kwargs: Kwargs) -> Result<String, Error> {
+    let indent: Option<usize> = kwargs.get("indent")?;
+    let sort_keys: Option<bool> = kwargs.get("sort_keys")?;
+    kwargs.assert_all_used()?;
+    json_dumps(&value, indent, sort_keys.unwrap_or(false))
+}
+
+fn to_nice_json(value: Value, kwargs: Kwargs) -> Result<String, Error> {
+    let indent: Option<usize> = kwargs.get("indent")?;
+    let sort_keys: Option<bool> = kwargs.get("sort_keys")?;
+    kwargs.assert_all_used()?;
+    json_dumps(&value, Some(indent.unwrap_or(4)), sort_keys.unwrap_or(true))
+}
+
+/// Serialize like Python's `json.dumps`: `", "` and `": "` separators when
+/// compact, or pretty-printed with `indent` spaces.
+fn json_dumps(value: &Value, indent: Option<usize>, sort_keys: bool) -> Result<String, Error> {
+    let mut json = serde_json::to_value(value)
+        .map_err(|err| invalid(format!("cannot convert value to JSON: {err}")))?;
+    if sort_keys {
+        json = sort_json_keys(json);
+    }
+
+    let mut out = Vec::new();
+    let result = match indent {
+        Some(indent) => {
+            let indent = " ".repeat(indent);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            json.serialize(&mut serde_json::Serializer::with_formatter(
+                &mut out, formatter,
+            ))
+        }
+        None => json.serialize(&mut serde_json::Serializer::with_formatter(
+            &mut out,
+            PythonFormatter,
+        )),
+    };
+    result.map_err(|err| invalid(format!("cannot serialize JSON: {err}")))?;
+    Ok(String::from_utf8(out).expect("serde_json writes UTF-8"))
+}
+
+fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_json_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(sort_json_keys).collect())
+        }
+        other => other,
+    }
+}
+
+/// Compact formatting with Python's default separators.
+struct PythonFormatter;
+
+impl serde_json::ser::Formatter for PythonFormatter {
+    fn begin_array_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(b": ")
+    }
+}
+
+fn from_json(value: &str) -> Result<Value, Error> {
+    let json: serde_json::Value =
+        serde_json::from_str(value).map_err(|err| invalid(format!("invalid JSON: {err}")))?;
+    Ok(Value::from_serialize(json))
+}
+
+/// `to_yaml` and `to_nice_yaml`. Output is always block style with two
+/// space indentation; formatting arguments are accepted for compatibility.
+fn to_yaml(value: Value, kwargs: Kwargs) -> Result<String, Error> {
+    for option in ["indent", "width", "default_flow_style", "sort_keys"] {
+        let _: Option<Value> = kwargs.get(option)?;
+    }
+    kwargs.assert_all_used()?;
+    serde_yaml::to_string(&to_yaml_value(&value)?)
+        .map_err(|err| invalid(format!("cannot serialize YAML: {err}")))
+}
+
+fn from_yaml(value: &str) -> Result<Value, Error> {
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str(value).map_err(|err| invalid(format!("invalid YAML: {err}")))?;
+    Ok(Value::from_serialize(yaml))
+}
+
+fn from_yaml_all(value: &str) -> Result<Value, Error> {
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(value) {
+        let yaml = <serde_yaml::Value as serde::Deserialize>::deserialize(document)
+            .map_err(|err| invalid(format!("invalid YAML: {err}")))?;
+        documents.push(Value::from_serialize(yaml));
+    }
+    Ok(Value::from(documents))
+}
+
+fn b64encode(value: &str) -> String {
+    BASE64.encode(value)
+}
+
+fn b64decode(value: &str) -> Result<String, Error> {
+    let decoded = BASE64
+        .decode(value.trim())
+        .map_err(|err| invalid(format!("invalid base64: {err}")))?;
+    String::from_utf8(decoded).map_err(|_| invalid("base64 decoded data is not valid UTF-8"))
+}
+
+fn hash(value: &str, algorithm: Option<&str>) -> Result<String, Error> {
+    let value = value.as_bytes();
+    Ok(match algorithm.unwrap_or("sha1") {
+        "md5" => hex::encode(Md5::digest(value)),
+        "sha1" => hex::encode(Sha1::digest(value)),
+        "sha224" => hex::encode(Sha224::digest(value)),
+        "sha256" => hex::encode(Sha256::digest(value)),
+        "sha384" => hex::encode(Sha384::digest(value)),
+        "sha512" => hex::encode(Sha512::digest(value)),
+        other => return Err(invalid(format!("unsupported hash algorithm '{other}'"))),
+    })
+}
+
+fn checksum(value: &str) -> String {
+    hex::encode(Sha1::digest(value.as_bytes()))
+}
+
+fn md5(value: &str) -> String {
+    hex::encode(Md5::digest(value.as_bytes()))
+}
+
+/// `password_hash(hashtype='sha512', salt=None, salt_size=None, rounds=None)`
+/// producing crypt(3) compatible `$6$` / `$5$` hashes.
+fn password_hash(
+    value: &str,
+    hashtype: Option<&str>,
+    salt: Option<&str>,
+    kwargs: Kwargs,
+) -> Result<String, Error> {
+    let hashtype = kwargs.get::<Option<&str>>("hashtype")?.or(hashtype);
+    let salt = kwargs.get::<Option<&str>>("salt")?.or(salt);
+    let rounds: Option<u32> = kwargs.get("rounds")?;
+    let _: Option<usize> = kwargs.get("salt_size")?;
+    kwargs.assert_all_used()?;
+
+    let scheme = match hashtype.unwrap_or("sha512") {
+        "sha512" | "sha512_crypt" => crypt::Scheme::Sha512,
+        "sha256" | "sha256_crypt" => crypt::Scheme::Sha256,
+        other => {
+            return Err(invalid(format!(
+                "unsupported password hash type '{other}' (use sha512 or sha256)"
+            )));
+        }
+    };
+
+    let salt = match salt {
+        Some(salt) => salt.to_string(),
+        None => crypt::random_salt()
+            .map_err(|err| invalid(format!("failed to generate salt: {err}")))?,
+    };
+    Ok(crypt::hash(scheme, value.as_bytes(), &salt, rounds))
+}
+
+fn quote(value: &str) -> Result<String, Error> {
+    shlex::try_quote(value)
+        .map(|quoted| quoted.into_owned())
+        .map_err(|err| invalid(format!("cannot quote value: {err}")))
+}
+
+/// Compile a Python-style pattern with the `ignorecase` and `multiline`
+/// options Ansible's regex filters and tests accept.
+pub(super) fn build_regex(pattern: &str, kwargs: &Kwargs) -> Result<Regex, Error> {
+    let ignorecase: Option<bool> = kwargs.get("ignorecase")?;
+    let multiline: Option<bool> = kwargs.get("multiline")?;
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignorecase.unwrap_or(false))
+        .multi_line(multiline.unwrap_or(false))
+        .build()
+        .map_err(|err| invalid(format!("invalid regular expression '{pattern}': {err}")))
+}
+
+/// Translate a Python `re.sub` replacement (`\1`, `\g<name>`) into the
+/// `regex` crate's syntax.
+fn python_replacement(replacement: &str) -> String {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => out.push_str("$$"),
+            '\\' => match chars.peek().copied() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    let mut group = String::new();
+                    while let Some(digit) = chars.peek().copied().filter(char::is_ascii_digit) {
+                        group.push(digit);
+                        chars.next();
+                    }
+                    out.push_str(&format!("${{{group}}}"));
+                }
+                Some('g') => {
+                    chars.next();
+                    let mut name = String::new();
+                    if chars.peek() == Some(&'<') {
+                        chars.next();
+                        for c in chars.by_ref() {
+                            if c == '>' {
+                                break;
+                            }
+                            name.push(c);
+                        }
+                    }
+                    out.push_str(&format!("${{{name}}}"));
+                }
+                Some('n') => {
+                    chars.next();
+                    out.push('\n');
+                }
+                Some('t') => {
+                    chars.next();
+                    out.push('\t');
+                }
+                Some('\\') => {
+                    chars.next();
+                    out.push('\\');
+                }
+                _ => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn regex_replace(
+    value: &str,
+    pattern: &str,
+    replacement: Option<&str>,
+    kwargs: Kwargs,
+) -> Result<String, Error> {
+    let regex = build_regex(pattern, &kwargs)?;
+    let count: Option<usize> = kwargs.get("count")?;
+    kwargs.assert_all_used()?;
+
+    let replacement = python_replacement(replacement.unwrap_or(""));
+    Ok(regex
+        .replacen(value, count.unwrap_or(0), replacement.as_str())
+        .into_owned())
+}
+
+/// `regex_search(pattern, '\\1', '\\g<name>', ...)`: the first match, or
+/// the requested groups of it as a list. Returns none without a match.
+fn regex_search(
+    value: &str,
+    pattern: &str,
+    groups: Rest<String>,
+    kwargs: Kwargs,
+) -> Result<Value, Error> {
+    let regex = build_regex(pattern, &kwargs)?;
+    kwargs.assert_all_used()?;
+
+    let Some(captures) = regex.captures(value) else {
+        return Ok(Value::from(()));
+    };
+    if groups.is_empty() {
+        return Ok(Value::from(&captures[0]));
+    }
+
+    let mut values = Vec::new();
+    for group in groups.iter() {
+        let capture = if let Some(index) = group.strip_prefix('\\') {
+            match index.parse::<usize>() {
+                Ok(index) => captures.get(index),
+                Err(_) => index
+                    .strip_prefix("g<")
+                    .and_then(|name| name.strip_suffix('>'))
+                    .and_then(|name| captures.name(name)),
+            }
+        } else {
+            return Err(invalid(format!(
+                "unknown regex_search argument '{group}' (expected '\\\\N' or '\\\\g<name>')"
+            )));
+        };
+        values.push(capture.map_or(Value::from(()), |m| Value::from(m.as_str())));
+    }
+    Ok(Value::from(values))
+}
+
+/// `regex_findall(pattern)`: all matches; with capture groups, the groups of
+/// each match like Python's `re.findall`.
+fn regex_findall(value: &str, pattern: &str, kwargs: Kwargs) -> Result<Value, Error> {
+    let regex = build_regex(pattern, &kwargs)?;
+    kwargs.assert_all_used()?;
+
+    let group_count = regex.captures_len() - 1;
+    let matches = regex.captures_iter(value).map(|captures| {
+        let group = |index: usize| Value::from(captures.get(index).map_or("", |m| m.as_str()));
+        match group_count {
+            0 => group(0),
+            1 => group(1),
+            n => Value::from((1..=n).map(group).collect::<Vec<_>>()),
+        }
+    });
+    Ok(Value::from(matches.collect::<Vec<_>>()))
+}
+
+fn regex_escape(value: &str, re_type: Option<&str>) -> Result<String, Error> {
+    match re_type.unwrap_or("python") {
+        "python" => Ok(regex::escape(value)),
+        "posix_basic" => Ok(value
+            .chars()
+            .flat_map(|c| {
+                let escape = matches!(c, '\\' | '^' | '$' | '.' | '[' | ']' | '*');
+                escape.then_some('\\').into_iter().chain([c])
+            })
+            .collect()),
+        other => Err(invalid(format!("unsupported regex type '{other}'"))),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ListMerge {
+    Replace,
+    Keep,
+    Append,
+    Prepend,
+    AppendRp,
+    PrependRp,
+}
+
+/// `combine(*dicts, recursive=False, list_merge='replace')`.
+fn combine(value: Value, others: Rest<Value>, kwargs: Kwargs) -> Result<Value, Error> {
+    let recursive: Option<bool> = kwargs.get("recursive")?;
+    let list_merge = match kwargs
+        .get::<Option<&str>>("list_merge")?
+        .unwrap_or("replace")
+    {
+        "replace" => ListMerge::Replace,
+        "keep" => ListMerge::Keep,
+        "append" => ListMerge::Append,
+        "prepend" => ListMerge::Prepend,
+        "append_rp" => ListMerge::AppendRp,
+        "prepend_rp" => ListMerge::PrependRp,
+        other => return Err(invalid(format!("invalid list_merge option '{other}'"))),
+    };
+    kwargs.assert_all_used()?;
+
+    // Both `a | combine(b, c)` and `[a, b, c] | combine` are accepted
+    let mut dicts = Vec::new();
+    for value in std::iter::once(&value).chain(others.iter()) {
+        match to_yaml_value(value)? {
+            serde_yaml::Value::Sequence(items) => dicts.extend(items),
+            other => dicts.push(other),
+        }
+    }
+
+    let mut result = serde_yaml::Mapping::new();
+    for dict in dicts {
+        let serde_yaml::Value::Mapping(dict) = dict else {
+            return Err(invalid("combine expects dictionaries"));
+        };
+        merge_mappings(&mut result, dict, recursive.unwrap_or(false), list_merge);
+    }
+    Ok(Value::from_serialize(result))
+}
+
+fn merge_mappings(
+    target: &mut serde_yaml::Mapping,
+    other: serde_yaml::Mapping,
+    recursive: bool,
+    list_merge: ListMerge,
+) {
+    use serde_yaml::Value as Yaml;
+
+    for (key, value) in other {
+        match (target.get_mut(&key), value) {
+            (Some(Yaml::Mapping(existing)), Yaml::Mapping(value)) if recursive => {
+                merge_mappings(existing, value, recursive, list_merge);
+            }
+            (Some(Yaml::Sequence(existing)), Yaml::Sequence(value)) => {
+                merge_lists(existing, value, list_merge);
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_lists(
+    existing: &mut Vec<serde_yaml::Value>,
+    value: Vec<serde_yaml::Value>,
+    list_merge: ListMerge,
+) {
+    match list_merge {
+        ListMerge::Replace => *existing = value,
+        ListMerge::Keep => {}
+        ListMerge::Append => existing.extend(value),
+        ListMerge::Prepend => {
+            let tail = std::mem::replace(existing, value);
+            existing.extend(tail);
+        }
+        ListMerge::AppendRp => {
+            existing.retain(|item| !value.contains(item));
+            existing.extend(value);
+        }
+        ListMerge::PrependRp => {
+            let tail: Vec<_> = existing
+                .drain(..)
+                .filter(|item| !value.contains(item))
+                .collect();
+            *existing = value;
+            existing.extend(tail);
+        }
+    }
+}
+
+fn dict2items(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+    let key_name: Option<&str> = kwargs.get("key_name")?;
+    let value_name: Option<&str> = kwargs.get("value_name")?;
+    kwargs.assert_all_used()?;
+    let (key_name, value_name) = (key_name.unwrap_or("key"), value_name.unwrap_or("value"));
+
+    if value.kind() != ValueKind::Map {
+        return Err(invalid(format!(
+            "dict2items requires a dictionary, got {}",
+            value.kind()
+        )));
+    }
+
+    let mut items = Vec::new();
+    for key in value.try_iter()? {
+        let item = value.get_item(&key)?;
+        items.push(Value::from_iter([(key_name, key), (value_name, item)]));
+    }
+    Ok(Value::from(items))
+}
+
+fn items2dict(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+    let key_name: Option<&str> = kwargs.get("key_name")?;
+    let value_name: Option<&str> = kwargs.get("value_name")?;
+    kwargs.assert_all_used()?;
+    let (key_name, value_name) = (
+        Value::from(key_name.unwrap_or("key")),
+        Value::from(value_name.unwrap_or("value")),
+    );
+
+    let mut entries = Vec::new();
+    for item in value.try_iter()? {
+        let key = item.get_item(&key_name)?;
+        let value = item.get_item(&value_name)?;
+        if key.is_undefined() || value.is_undefined() {
+            return Err(invalid(format!(
+                "items2dict requires each item to have '{key_name}' and '{value_name}'"
+            )));
+        }
+        entries.push((key, value));
+    }
+    Ok(Value::from_iter(entries))
+}
+
+fn flatten(value: Value, levels: Option<usize>, kwargs: Kwargs) -> Result<Value, Error> {
+    let skip_nulls: Option<bool> = kwargs.get("skip_nulls")?;
+    kwargs.assert_all_used()?;
+
+    fn flatten_into(
+        value: Value,
+        levels: Option<usize>,
+        skip_nulls: bool,
+        out: &mut Vec<Value>,
+    ) -> Result<(), Error> {
+        for item in value.try_iter()? {
+            if item.kind() == ValueKind::Seq && levels != Some(0) {
+                flatten_into(item, levels.map(|levels| levels - 1), skip_nulls, out)?;
+            } else if !(skip_nulls && item.is_none()) {
+                out.push(item);
+            }
+        }
+        Ok(())
+    }
+
+    let mut out = Vec::new();
+    flatten_into(value, levels, skip_nulls.unwrap_or(true), &mut out)?;
+    Ok(Value::from(out))
+}
+
+fn unique_items(items: impl IntoIterator<Item = Value>) -> Vec<Value> {
+    let mut out: Vec<Value> = Vec::new();
+    for item in items {
+        if !out.contains(&item) {
+            out.push(item);
+        }
+    }
+    out
+}
+
+fn union(value: Value, other: Value) -> Result<Value, Error> {
+    Ok(Value::from(unique_items(
+        value.try_iter()?.chain(other.try_iter()?),
+    )))
+}
+
+fn intersect(value: Value, other: Value) -> Result<Value, Error> {
+    let other: Vec<Value> = other.try_iter()?.collect();
+    Ok(Value::from(unique_items(
+        value.try_iter()?.filter(|item| other.contains(item)),
+    )))
+}
+
+fn difference(value: Value, other: Value) -> Result<Value, Error> {
+    let other: Vec<Value> = other.try_iter()?.collect();
+    Ok(Value::from(unique_items(
+        value.try_iter()?.filter(|item| !other.contains(item)),
+    )))
+}
+
+fn symmetric_difference(value: Value, other: Value) -> Result<Value, Error> {
+    let left: Vec<Value> = value.try_iter()?.collect();
+    let right: Vec<Value> = other.try_iter()?.collect();
+    Ok(Value::from(unique_items(
+        left.iter()
+            .filter(|item| !right.contains(item))
+            .chain(right.iter().filter(|item| !left.contains(item)))
+            .cloned(),
+    )))
+}
+
+/// `key | extract(container, morekeys)`, typically used with `map`.
+fn extract(key: Value, container: Value, morekeys: Option<Value>) -> Result<Value, Error> {
+    let mut value = container.get_item(&key)?;
+    if let Some(morekeys) = morekeys {
+        let keys: Vec<Value> = match morekeys.kind() {
+            ValueKind::Seq => morekeys.try_iter()?.collect(),
+            _ => vec![morekeys],
+        };
+        for key in keys {
+            value = value.get_item(&key)?;
+        }
+    }
+    Ok(value)
+}
+
+fn mandatory(value: Value, msg: Option<String>) -> Result<Value, Error> {
+    if value.is_undefined() {
+        return Err(Error::new(
+            ErrorKind::UndefinedError,
+            msg.unwrap_or_else(|| "Mandatory variable has not been defined".to_string()),
+        ));
+    }
+    Ok(value)
+}
+
+fn ternary(
+    value: Value,
+    true_value: Value,
+    false_value: Value,
+    none_value: Option<Value>,
+) -> Value {
+    match none_value {
+        Some(none_value) if value.is_none() => none_value,
+        _ if value.is_true() => true_value,
+        _ => false_value,
+    }
+}
+
+/// Ansible's `bool`: strings like `yes`, `on` and `1` are true.
+fn to_bool(value: Value) -> bool {
+    match value.kind() {
+        ValueKind::String => matches!(
+            value
+                .as_str()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+                .as_str(),
+            "yes" | "on" | "1" | "true" | "y" | "t"
+        ),
+        ValueKind::Number => value.as_i64() == Some(1),
+        _ => value.is_true(),
+    }
+}
+
+/// The Python type name Ansible would report for the value.
+fn type_debug(value: Value) -> &'static str {
+    match value.kind() {
+        ValueKind::Undefined => "AnsibleUndefined",
+        ValueKind::None => "NoneType",
+        ValueKind::Bool => "bool",
+        ValueKind::Number if value.is_integer() => "int",
+        ValueKind::Number => "float",
+        ValueKind::String => "str",
+        ValueKind::Bytes => "bytes",
+        ValueKind::Seq | ValueKind::Iterable => "list",
+        ValueKind::Map => "dict",
+        _ => "object",
+    }
+}
+
+fn basename(value: &str) -> String {
+    // Python keeps everything after the last slash, so `a/b/` has none
+    match value.rfind('/') {
+        Some(index) => value[index + 1..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn dirname(value: &str) -> String {
+    // Python keeps everything before the last slash, including for `a/b/`
+    match value.rfind('/') {
+        Some(0) => "/".to_string(),
+        Some(index) => value[..index].to_string(),
+        None => String::new(),
+    }
+}
+
+fn expanduser(value: &str) -> String {
+    match (value.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{home}{rest}")
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn splitext(value: &str) -> Value {
+    let name_start = value.rfind('/').map_or(0, |index| index + 1);
+    let name = &value[name_start..];
+    match name.rfind('.') {
+        Some(dot) if name[..dot].chars().any(|c| c != '.') => {
+            let split = name_start + dot;
+            Value::from(vec![value[..split].to_string(), value[split..].to_string()])
+        }
+        _ => Value::from(vec![value.to_string(), String::new()]),
+    }
+}
@@ -0,0 +1,252 @@
+//! The `ipaddr`, `ipv4` and `ipv6` filters from `ansible.utils`, covering
+//! the commonly used queries.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use minijinja::{Error, ErrorKind, value::Value};
+
+/// An address as written, with the prefix length if one was given.
+struct Address {
+    ip: IpAddr,
+    net: IpNet,
+    has_prefix: bool,
+}
+
+impl Address {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            let net = IpNet::from(ip);
+            return Some(Self {
+                ip,
+                net,
+                has_prefix: false,
+            });
+        }
+
+        let (ip, prefix) = value.split_once('/')?;
+        let ip = ip.parse::<IpAddr>().ok()?;
+        let net = match prefix.parse::<u8>() {
+            Ok(prefix) => IpNet::new(ip, prefix).ok()?,
+            Err(_) => IpNet::with_netmask(ip, prefix.parse::<IpAddr>().ok()?).ok()?,
+        };
+        Some(Self {
+            ip,
+            net,
+            has_prefix: true,
+        })
+    }
+
+    fn version(&self) -> u8 {
+        match self.ip {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 6,
+        }
+    }
+
+    fn prefix(&self) -> u8 {
+        self.net.prefix_len()
+    }
+
+    fn size(&self) -> u128 {
+        let host_bits = u32::from(self.net.max_prefix_len() - self.prefix());
+        1u128.checked_shl(host_bits).unwrap_or(u128::MAX)
+    }
+
+    fn is_network(&self) -> bool {
+        self.size() > 1 && self.ip == self.net.network()
+    }
+
+    fn cidr(&self) -> String {
+        format!("{}/{}", self.net.network(), self.prefix())
+    }
+
+    fn display(&self) -> String {
+        if self.has_prefix {
+            format!("{}/{}", self.ip, self.prefix())
+        } else {
+            self.ip.to_string()
+        }
+    }
+
+    /// The `offset`th address of the network; negative offsets count back
+    /// from the last address.
+    fn nth(&self, offset: i128) -> Option<IpAddr> {
+        let size = i128::try_from(self.size()).ok()?;
+        let index = if offset < 0 { size + offset } else { offset };
+        if !(0..size).contains(&index) {
+            return None;
+        }
+        Some(add(self.net.network(), index as u128))
+    }
+
+    fn is_private(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => {
+                ip.is_private() || ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64
+            }
+            IpAddr::V6(ip) => ip.segments()[0] & 0xfe00 == 0xfc00,
+        }
+    }
+
+    fn is_link_local(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        }
+    }
+
+    fn is_public(&self) -> bool {
+        !(self.is_private()
+            || self.is_link_local()
+            || self.ip.is_loopback()
+            || self.ip.is_multicast()
+            || self.ip.is_unspecified())
+    }
+
+    fn revdns(&self) -> String {
+        match self.ip {
+            IpAddr::V4(ip) => {
+                let [a, b, c, d] = ip.octets();
+                format!("{d}.{c}.{b}.{a}.in-addr.arpa.")
+            }
+            IpAddr::V6(ip) => {
+                let mut name = String::new();
+                for byte in ip.octets().iter().rev() {
+                    name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+                }
+                name.push_str("ip6.arpa.");
+                name
+            }
+        }
+    }
+}
+
+fn add(ip: IpAddr, offset: u128) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4((u32::from(ip).wrapping_add(offset as u32)).into()),
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip).wrapping_add(offset)).into()),
+    }
+}
+
+/// `ipaddr(value, query)`: validate and query an address or a list of
+/// addresses. Invalid input yields `false`; lists keep only the matches.
+pub fn ipaddr(value: Value, query: Option<Value>) -> Result<Value, Error> {
+    filter(value, query, None)
+}
+
+pub fn ipv4(value: Value, query: Option<Value>) -> Result<Value, Error> {
+    filter(value, query, Some(4))
+}
+
+pub fn ipv6(value: Value, query: Option<Value>) -> Result<Value, Error> {
+    filter(value, query, Some(6))
+}
+
+fn filter(value: Value, query: Option<Value>, version: Option<u8>) -> Result<Value, Error> {
+    if value.as_str().is_none()
+        && let Ok(items) = value.try_iter()
+    {
+        let mut results = Vec::new();
+        for item in items {
+            let result = filter(item, query.clone(), version)?;
+            if result.is_true() {
+                results.push(result);
+            }
+        }
+        return Ok(Value::from(results));
+    }
+
+    let Some(address) = value.as_str().and_then(Address::parse) else {
+        return Ok(Value::from(false));
+    };
+    if version.is_some_and(|version| version != address.version()) {
+        return Ok(Value::from(false));
+    }
+
+    query_address(&value, &address, query.as_ref())
+}
+
+fn query_address(value: &Value, address: &Address, query: Option<&Value>) -> Result<Value, Error> {
+    let query = match query {
+        None => return Ok(Value::from(address.display())),
+        Some(query) if query.is_none() || query.is_undefined() => {
+            return Ok(Value::from(address.display()));
+        }
+        Some(query) => query,
+    };
+
+    if let Some(offset) = query.as_i64() {
+        return Ok(nth(address, i128::from(offset)));
+    }
+
+    let query = query.as_str().unwrap_or_default();
+    if let Ok(offset) = query.parse::<i128>() {
+        return Ok(nth(address, offset));
+    }
+
+    let matches = |condition: bool| when(condition, value.clone());
+    let size = address.size();
+
+    Ok(match query {
+        "" => Value::from(address.display()),
+        "address" => when(!address.is_network(), address.ip.to_string()),
+        "host" => when(
+            !address.is_network(),
+            format!("{}/{}", address.ip, address.prefix()),
+        ),
+        "address/prefix" | "host/prefix" => when(
+            size > 1 && !address.is_network(),
+            format!("{}/{}", address.ip, address.prefix()),
+        ),
+        "network" => Value::from(address.net.network().to_string()),
+        "net" => when(address.is_network(), address.cidr()),
+        "cidr" | "subnet" | "network/prefix" => Value::from(address.cidr()),
+        "netmask" => Value::from(address.net.netmask().to_string()),
+        "hostmask" => Value::from(address.net.hostmask().to_string()),
+        "prefix" => Value::from(address.prefix()),
+        "broadcast" => when(size > 2, address.net.broadcast().to_string()),
+        "size" => Value::from(size),
+        "first_usable" => when(size > 2, add(address.net.network(), 1).to_string()),
+        "last_usable" => when(size > 2, add(address.net.network(), size - 2).to_string()),
+        "size_usable" => Value::from(size.saturating_sub(2)),
+        "version" => Value::from(address.version()),
+        "ipv4" | "v4" => matches(address.version() == 4),
+        "ipv6" | "v6" => matches(address.version() == 6),
+        "private" => matches(address.is_private()),
+        "public" => matches(address.is_public()),
+        "loopback" => matches(address.ip.is_loopback()),
+        "multicast" => matches(address.ip.is_multicast()),
+        "link-local" => matches(address.is_link_local()),
+        "unicast" => matches(!address.ip.is_multicast()),
+        "revdns" => Value::from(address.revdns()),
+        "bool" => Value::from(true),
+        other => match Address::parse(other) {
+            // A network as query tests for membership
+            Some(network) => matches(network.net.contains(&address.ip)),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("unknown ipaddr query '{other}'"),
+                ));
+            }
+        },
+    })
+}
+
+fn nth(address: &Address, offset: i128) -> Value {
+    match address.nth(offset) {
+        Some(ip) => Value::from(format!("{ip}/{}", address.prefix())),
+        None => Value::from(false),
+    }
+}
+
+/// `value` if `condition` holds, `false` otherwise.
+fn when(condition: bool, value: impl Into<Value>) -> Value {
+    if condition {
+        value.into()
+    } else {
+        Value::from(false)
+    }
+}
@@ -0,0 +1,546 @@
+//! The `lookup`/`query` functions and the lookup plugins they dispatch to.
+//!
+//! Lookups run on the controller. Relative paths are resolved against
+//! `ansible_search_path` (the current role directories, then the playbook
+//! directory) the same way tasks resolve their local files.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use minijinja::{
+    Environment, Error, ErrorKind, State,
+    value::{Kwargs, Rest, Value, ValueKind},
+};
+
+use super::{crypt, filters::invalid};
+use crate::task::copy::find_file;
+
+const DEFAULT_PASSWORD_LENGTH: usize = 20;
+const DEFAULT_PASSWORD_CHARS: [&str; 3] = ["ascii_letters", "digits", ".,:-_"];
+
+pub(super) fn register(env: &mut Environment<'_>) {
+    env.add_function("lookup", lookup);
+    env.add_function("query", query);
+    env.add_function("q", query);
+}
+
+/// `lookup(name, *terms, wantlist=False, **options)`: list results are
+/// joined with commas unless `wantlist` is set.
+fn lookup(state: &State, name: &str, terms: Rest<Value>, kwargs: Kwargs) -> Result<Value, Error> {
+    let wantlist: Option<bool> = kwargs.get("wantlist")?;
+    let values = run(state, name, &terms, &kwargs)?;
+    kwargs.assert_all_used()?;
+
+    if wantlist.unwrap_or(false) {
+        return Ok(Value::from(values));
+    }
+    Ok(match values.len() {
+        0 => Value::from(()),
+        1 => values.into_iter().next().expect("one value"),
+        _ => Value::from(
+            values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    })
+}
+
+/// `query(name, *terms, **options)`: always returns a list.
+fn query(state: &State, name: &str, terms: Rest<Value>, kwargs: Kwargs) -> Result<Value, Error> {
+    let values = run(state, name, &terms, &kwargs)?;
+    kwargs.assert_all_used()?;
+    Ok(Value::from(values))
+}
+
+fn run(state: &State, name: &str, terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let name = name.strip_prefix("ansible.builtin.").unwrap_or(name);
+    let terms = flatten_terms(terms)?;
+    match name {
+        "file" => file(state, &terms, kwargs),
+        "env" => env(&terms, kwargs),
+        "template" => template(state, &terms, kwargs),
+        "pipe" => pipe(state, &terms),
+        "lines" => lines(state, &terms),
+        "fileglob" => fileglob(state, &terms),
+        "first_found" => first_found(state, &terms, kwargs),
+        "password" => password(state, &terms, kwargs),
+        other => Err(invalid(format!("lookup plugin '{other}' not found"))),
+    }
+}
+
+/// Terms may be given as separate arguments or as a list.
+fn flatten_terms(terms: &[Value]) -> Result<Vec<Value>, Error> {
+    let mut flat = Vec::new();
+    for term in terms {
+        if term.kind() == ValueKind::Seq {
+            flat.extend(term.try_iter()?);
+        } else {
+            flat.push(term.clone());
+        }
+    }
+    Ok(flat)
+}
+
+fn term_str(term: &Value) -> Result<&str, Error> {
+    term.as_str()
+        .ok_or_else(|| invalid(format!("lookup term must be a string, got {}", term.kind())))
+}
+
+fn search_path(state: &State) -> Vec<PathBuf> {
+    let Some(paths) = state.lookup("ansible_search_path") else {
+        return vec![PathBuf::from(".")];
+    };
+    paths
+        .try_iter()
+        .map(|paths| {
+            paths
+                .filter_map(|path| path.as_str().map(PathBuf::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn playbook_dir(state: &State) -> PathBuf {
+    state
+        .lookup("playbook_dir")
+        .and_then(|dir| dir.as_str().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+fn find(state: &State, subdirectory: &str, name: &str) -> Result<PathBuf, Error> {
+    find_file(&search_path(state), subdirectory, name).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("could not locate file in lookup: {name}"),
+        )
+    })
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+        .map_err(|err| invalid(format!("failed to read {}: {err}", path.display())))
+}
+
+fn file(state: &State, terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let rstrip: Option<bool> = kwargs.get("rstrip")?;
+    let lstrip: Option<bool> = kwargs.get("lstrip")?;
+
+    let mut values = Vec::new();
+    for term in terms {
+        let mut contents = read_file(&find(state, "files", term_str(term)?)?)?;
+        if rstrip.unwrap_or(true) {
+            contents.truncate(contents.trim_end().len());
+        }
+        if lstrip.unwrap_or(false) {
+            contents = contents.trim_start().to_string();
+        }
+        values.push(Value::from(contents));
+    }
+    Ok(values)
+}
+
+fn env(terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let default: Option<Value> = kwargs.get("default")?;
+    terms
+        .iter()
+        .map(|term| {
+            Ok(match std::env::var(term_str(term)?) {
+                Ok(value) => Value::from(value),
+                Err(_) => default.clone().unwrap_or_else(|| Value::from("")),
+            })
+        })
+        .collect()
+}
+
+/// Render template files with the current variables, plus `template_vars`.
+fn template(state: &State, terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let template_vars: Option<Value> = kwargs.get("template_vars")?;
+    let _: Option<bool> = kwargs.get("convert_data")?;
+
+    let mut context: BTreeMap<String, Value> = state
+        .known_variables()
+        .into_iter()
+        .filter_map(|name| {
+            let value = state.lookup(&name)?;
+            Some((name.into_owned(), value))
+        })
+        .collect();
+    if let Some(template_vars) = template_vars {
+        for key in template_vars.try_iter()? {
+            if let Some(name) = key.as_str() {
+                context.insert(name.to_string(), template_vars.get_item(&key)?);
+            }
+        }
+    }
+    let context = Value::from(context);
+
+    let mut values = Vec::new();
+    for term in terms {
+        let path = find(state, "templates", term_str(term)?)?;
+        let source = read_file(&path)?;
+        let rendered =
+            state
+                .env()
+                .render_named_str(&path.to_string_lossy(), &source, context.clone())?;
+        values.push(Value::from(rendered));
+    }
+    Ok(values)
+}
+
+/// Run a command through the controller's shell in the playbook directory.
+fn run_command(state: &State, command: &str) -> Result<String, Error> {
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(playbook_dir(state))
+        .output()
+        .map_err(|err| invalid(format!("failed to run '{command}': {err}")))?;
+    if !output.status.success() {
+        return Err(invalid(format!(
+            "lookup command '{command}' exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| invalid(format!("output of '{command}' is not valid UTF-8")))
+}
+
+fn pipe(state: &State, terms: &[Value]) -> Result<Vec<Value>, Error> {
+    terms
+        .iter()
+        .map(|term| {
+            let output = run_command(state, term_str(term)?)?;
+            Ok(Value::from(output.trim_end()))
+        })
+        .collect()
+}
+
+fn lines(state: &State, terms: &[Value]) -> Result<Vec<Value>, Error> {
+    let mut values = Vec::new();
+    for term in terms {
+        let output = run_command(state, term_str(term)?)?;
+        values.extend(output.lines().map(Value::from));
+    }
+    Ok(values)
+}
+
+/// Files (not directories) matching a wildcard in the file name part.
+fn fileglob(state: &State, terms: &[Value]) -> Result<Vec<Value>, Error> {
+    let mut values = Vec::new();
+    for term in terms {
+        let term = Path::new(term_str(term)?);
+        let pattern = term
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let directory = term.parent().unwrap_or(Path::new(""));
+        let directory = if directory.as_os_str().is_empty() {
+            search_path(state)
+                .into_iter()
+                .map(|dir| dir.join("files"))
+                .find(|dir| dir.is_dir())
+        } else {
+            find_file(&search_path(state), "files", &directory.to_string_lossy())
+        };
+        let Some(directory) = directory else {
+            continue;
+        };
+
+        let mut matches: Vec<PathBuf> = std::fs::read_dir(&directory)
+            .map_err(|err| invalid(format!("failed to read {}: {err}", directory.display())))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| super::wildcard_match(&pattern, &name.to_string_lossy()))
+            })
+            .collect();
+        matches.sort();
+        values.extend(matches.into_iter().map(|path| {
+            Value::from(
+                std::path::absolute(&path)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        }));
+    }
+    Ok(values)
+}
+
+/// `first_found`: terms are file names or `{files, paths, skip}` mappings;
+/// `files`, `paths` and `skip` may also be passed as options.
+fn first_found(state: &State, terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let option_files: Option<Value> = kwargs.get("files")?;
+    let option_paths: Option<Value> = kwargs.get("paths")?;
+    let option_skip: Option<bool> = kwargs.get("skip")?;
+
+    let split = |value: &Value| -> Result<Vec<String>, Error> {
+        let items = match value.kind() {
+            ValueKind::Seq => value.try_iter()?.collect(),
+            _ => vec![value.clone()],
+        };
+        Ok(items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .flat_map(|item| item.split([',', ';']))
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect())
+    };
+
+    let mut files = Vec::new();
+    let mut paths = Vec::new();
+    let mut skip = option_skip.unwrap_or(false);
+    for term in terms {
+        if term.kind() == ValueKind::Map {
+            if let Some(value) = term
+                .get_item(&Value::from("files"))
+                .ok()
+                .filter(|v| !v.is_undefined())
+            {
+                files.extend(split(&value)?);
+            }
+            if let Some(value) = term
+                .get_item(&Value::from("paths"))
+                .ok()
+                .filter(|v| !v.is_undefined())
+            {
+                paths.extend(split(&value)?);
+            }
+            if let Ok(value) = term.get_item(&Value::from("skip")) {
+                skip |= value.is_true();
+            }
+        } else {
+            files.push(term_str(term)?.to_string());
+        }
+    }
+    if let Some(value) = &option_files {
+        files.extend(split(value)?);
+    }
+    if let Some(value) = &option_paths {
+        paths.extend(split(value)?);
+    }
+
+    let candidates: Vec<String> = if paths.is_empty() {
+        files
+    } else {
+        paths
+            .iter()
+            .flat_map(|path| files.iter().map(move |file| format!("{path}/{file}")))
+            .collect()
+    };
+
+    for candidate in &candidates {
+        if let Some(path) = find_file(&search_path(state), "files", candidate) {
+            let path = std::path::absolute(&path).unwrap_or(path);
+            return Ok(vec![Value::from(path.to_string_lossy().into_owned())]);
+        }
+    }
+
+    if skip {
+        return Ok(Vec::new());
+    }
+    Err(invalid(format!(
+        "no file was found when using first_found: {}",
+        candidates.join(", ")
+    )))
+}
+
+/// Characters for the password lookup's `chars` option: named Python
+/// string constants or literal characters.
+fn password_chars(sets: &[String]) -> Vec<char> {
+    let mut chars = Vec::new();
+    for set in sets {
+        match set.as_str() {
+            "ascii_letters" => chars.extend(('a'..='z').chain('A'..='Z')),
+            "ascii_lowercase" => chars.extend('a'..='z'),
+            "ascii_uppercase" => chars.extend('A'..='Z'),
+            "digits" => chars.extend('0'..='9'),
+            "hexdigits" => chars.extend(('0'..='9').chain('a'..='f').chain('A'..='F')),
+            "octdigits" => chars.extend('0'..='7'),
+            "punctuation" => chars.extend("!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".chars()),
+            literal => chars.extend(literal.chars()),
+        }
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    chars
+}
+
+/// Split a `chars=` term option; a literal comma is written as `,,`.
+fn split_char_sets(option: &str) -> Vec<String> {
+    option
+        .replace(",,", "\0")
+        .split(',')
+        .map(|set| set.replace('\0', ","))
+        .collect()
+}
+
+fn random_password(length: usize, chars: &[char]) -> Result<String, Error> {
+    if chars.is_empty() {
+        return Err(invalid("password lookup has no characters to choose from"));
+    }
+    // Rejection sampling keeps the distribution uniform
+    let limit = 256 - 256 % chars.len();
+    let mut password = Vec::with_capacity(length);
+    let mut buffer = [0u8; 64];
+    while password.len() < length {
+        getrandom::fill(&mut buffer)
+            .map_err(|err| invalid(format!("failed to generate password: {err}")))?;
+        password.extend(
+            buffer
+                .iter()
+                .map(|byte| usize::from(*byte))
+                .filter(|byte| *byte < limit)
+                .map(|byte| chars[byte % chars.len()]),
+        );
+    }
+    password.truncate(length);
+    Ok(password.into_iter().collect())
+}
+
+/// `password`: read the password stored at a path, or generate and store a
+/// new one. Terms are `path [length=N] [chars=...] [encrypt=...]`; the same
+/// options may be given as keyword arguments. `/dev/null` never persists.
+fn password(state: &State, terms: &[Value], kwargs: &Kwargs) -> Result<Vec<Value>, Error> {
+    let kwarg_length: Option<usize> = kwargs.get("length")?;
+    let kwarg_chars: Option<Value> = kwargs.get("chars")?;
+    let kwarg_encrypt: Option<String> = kwargs.get("encrypt")?;
+    let _: Option<Value> = kwargs.get("seed")?;
+
+    let mut values = Vec::new();
+    for term in terms {
+        let mut parts = term_str(term)?.split_whitespace();
+        let path = parts
+            .next()
+            .ok_or_else(|| invalid("password lookup requires a path"))?;
+
+        let mut length = kwarg_length.unwrap_or(DEFAULT_PASSWORD_LENGTH);
+        let mut chars = match &kwarg_chars {
+            Some(chars) if chars.kind() == ValueKind::Seq => {
+                chars.try_iter()?.map(|set| set.to_string()).collect()
+            }
+            Some(chars) => vec![chars.to_string()],
+            None => DEFAULT_PASSWORD_CHARS.map(String::from).to_vec(),
+        };
+        let mut encrypt = kwarg_encrypt.clone();
+        for option in parts {
+            match option.split_once('=') {
+                Some(("length", value)) => {
+                    length = value
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid password length '{value}'")))?;
+                }
+                Some(("chars", value)) => chars = split_char_sets(value),
+                Some(("encrypt", value)) => encrypt = Some(value.to_string()),
+                Some(("seed", _)) => {}
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown password lookup option '{option}'"
+                    )));
+                }
+            }
+        }
+
+        let path = if Path::new(path).is_relative() {
+            playbook_dir(state).join(path)
+        } else {
+            PathBuf::from(path)
+        };
+
+        let (password, salt) = if path == Path::new("/dev/null") {
+            (random_password(length, &password_chars(&chars))?, None)
+        } else {
+            stored_password(&path, length, &chars, encrypt.is_some())?
+        };
+
+        let value = match encrypt.as_deref() {
+            None => password,
+            Some(scheme) => {
+                let scheme = match scheme {
+                    "sha512_crypt" => crypt::Scheme::Sha512,
+                    "sha256_crypt" => crypt::Scheme::Sha256,
+                    other => {
+                        return Err(invalid(format!(
+                            "unsupported password encrypt scheme '{other}'"
+                        )));
+                    }
+                };
+                let salt = match salt {
+                    Some(salt) => salt,
+                    None => crypt::random_salt()
+                        .map_err(|err| invalid(format!("failed to generate salt: {err}")))?,
+                };
+                crypt::hash(scheme, password.as_bytes(), &salt, None)
+            }
+        };
+        values.push(Value::from(value));
+    }
+    Ok(values)
+}
+
+/// Read `password [salt=...]` from `path`, creating it with a new password
+/// (and salt, when encrypting) if it does not exist yet.
+fn stored_password(
+    path: &Path,
+    length: usize,
+    chars: &[String],
+    with_salt: bool,
+) -> Result<(String, Option<String>), Error> {
+    if let Ok(contents) = std::fs::read_to_string(path) {
+        let line = contents.lines().next().unwrap_or_default();
+        let (password, salt) = match line.rsplit_once(" salt=") {
+            Some((password, salt)) => (password.to_string(), Some(salt.to_string())),
+            None => (line.to_string(), None),
+        };
+        if with_salt && salt.is_none() {
+            let salt = crypt::random_salt()
+                .map_err(|err| invalid(format!("failed to generate salt: {err}")))?;
+            write_password_file(path, &format!("{password} salt={salt}\n"))?;
+            return Ok((password, Some(salt)));
+        }
+        return Ok((password, salt));
+    }
+
+    let password = random_password(length, &password_chars(chars))?;
+    let salt = if with_salt {
+        Some(
+            crypt::random_salt()
+                .map_err(|err| invalid(format!("failed to generate salt: {err}")))?,
+        )
+    } else {
+        None
+    };
+    let contents = match &salt {
+        Some(salt) => format!("{password} salt={salt}\n"),
+        None => format!("{password}\n"),
+    };
+    write_password_file(path, &contents)?;
+    Ok((password, salt))
+}
+
+fn write_password_file(path: &Path, contents: &str) -> Result<(), Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| invalid(format!("failed to create {}: {err}", parent.display())))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|err| invalid(format!("failed to write {}: {err}", path.display())))?;
+    file.write_all(contents.as_bytes())
+        .map_err(|err| invalid(format!("failed to write {}: {err}", path.display())))
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use eyre::{Context, eyre};
+use minijinja::value::{Enumerator, Object};
+use serde_yaml::{
+    Value,
+    value::{Tag, TaggedValue},
+};
+use tracing::trace;
+
+mod crypt;
+mod filters;
+mod ipaddr;
+mod lookups;
+mod tests;
+
+/// Name prefix of templated strings in the inline environment, whose loader
+/// treats the rest of the name as the template source.
+const INLINE_PREFIX: &str = "<inline>";
+
+/// The MiniJinja environment of a run, with Ansible's filters, tests and
+/// lookups registered once. Templated strings are compiled on first use and
+/// cached by their source, so repeated tasks and variables are parsed once.
+#[derive(Clone, Debug)]
+pub struct Engine {
+    inner: Arc<EngineInner>,
+}
+
+#[derive(Debug)]
+struct EngineInner {
+    base: minijinja::Environment<'static>,
+    inline: minijinja::Environment<'static>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        let mut base = minijinja::Environment::new();
+        base.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+        // Ansible never escapes output, whatever the template's extension
+        base.set_auto_escape_callback(|_| minijinja::AutoEscape::None);
+        filters::register(&mut base);
+        tests::register(&mut base);
+        lookups::register(&mut base);
+
+        let mut inline = base.clone();
+        inline.set_loader(|name| Ok(name.strip_prefix(INLINE_PREFIX).map(str::to_owned)));
+
+        Self {
+            inner: Arc::new(EngineInner { base, inline }),
+        }
+    }
+}
+
+impl Engine {
+    /// A copy of the environment without cached templates, for renders that
+    /// need their own loader or syntax.
+    pub fn environment(&self) -> minijinja::Environment<'static> {
+        self.inner.base.clone()
+    }
+
+    fn render(&self, source: &str, context: &minijinja::Value) -> Result<String, minijinja::Error> {
+        self.inner
+            .inline
+            .get_template(&format!("{INLINE_PREFIX}{source}"))?
+            .render(context)
+    }
+
+    fn evaluate(
+        &self,
+        expression: &str,
+        context: &minijinja::Value,
+    ) -> Result<bool, minijinja::Error> {
+        let value = self
+            .inner
+            .base
+            .compile_expression(expression)?
+            .eval(context)?;
+        Ok(value.is_true())
+    }
+}
+
+fn has_template(s: &str) -> bool {
+    s.contains("{{") || s.contains("{%")
+}
+
+/// Render a single string through minijinja if it contains template syntax.
+fn render_string(engine: &Engine, s: &str, context: &minijinja::Value) -> eyre::Result<String> {
+    if !has_template(s) {
+        return Ok(s.to_owned());
+    }
+
+    engine
+        .render(s, context)
+        .wrap_err_with(|| format!("failed to render template: {s}"))
+}
+
+fn render_value_inner(
+    engine: &Engine,
+    value: &Value,
+    context: &minijinja::Value,
+) -> eyre::Result<Value> {
+    match value {
+        Value::String(s) => {
+            let rendered = render_string(engine, s, context)?;
+            Ok(Value::String(rendered))
+        }
+        Value::Sequence(seq) => {
+            let rendered: eyre::Result<Vec<Value>> = seq
+                .iter()
+                .map(|v| render_value_inner(engine, v, context))
+                .collect();
+            Ok(Value::Sequence(rendered?))
+        }
+        Value::Mapping(map) => {
+            let mut rendered = serde_yaml::Mapping::new();
+            for (k, v) in map {
+                rendered.insert(k.clone(), render_value_inner(engine, v, context)?);
+            }
+            Ok(Value::Mapping(rendered))
+        }
+        Value::Tagged(tagged) if tagged.tag == "vault" => {
+            let Value::String(ciphertext) = &tagged.value else {
+                return Err(eyre!("!vault value must be a string"));
+            };
+            let plaintext = kerosene::vault::decrypt(ciphertext.as_bytes())
+                .wrap_err("failed to decrypt !vault value")?;
+            Ok(Value::String(
+                String::from_utf8(plaintext).wrap_err("!vault value is not valid UTF-8")?,
+            ))
+        }
+        // Literal values are never rendered
+        Value::Tagged(tagged) if tagged.tag == "unsafe" => Ok(tagged.value.clone()),
+        // Number, Bool, Null, other tags — pass through
+        other => Ok(other.clone()),
+    }
+}
+
+/// Tag every string in `value` as `!unsafe`, so that data which came from a
+/// host or was already rendered is never evaluated as a template again.
+pub fn mark_unsafe(value: Value) -> Value {
+    match value {
+        Value::String(_) => Value::Tagged(Box::new(TaggedValue {
+            tag: Tag::new("unsafe"),
+            value,
+        })),
+        Value::Sequence(seq) => Value::Sequence(seq.into_iter().map(mark_unsafe).collect()),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (key, mark_unsafe(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Variables that are rendered on first access and memoized, exposed to
+/// MiniJinja as the template context. A `Vars` is built for each task, so
+/// every variable is rendered at most once per task and only if referenced.
+#[derive(Clone, Debug)]
+pub struct Vars {
+    inner: Arc<LazyVars>,
+}
+
+#[derive(Debug)]
+struct LazyVars {
+    engine: Engine,
+    raw: HashMap<String, Value>,
+    resolved: Mutex<HashMap<String, minijinja::Value>>,
+    /// Variables currently being rendered, innermost last.
+    resolving: Mutex<Vec<String>>,
+    /// The first cycle found, reported instead of the nested render errors.
+    cycle: Mutex<Option<String>>,
+}
+
+impl Vars {
+    pub fn new(engine: &Engine, vars: HashMap<String, Value>) -> Self {
+        Self {
+            inner: Arc::new(LazyVars {
+                engine: engine.clone(),
+                raw: vars,
+                resolved: Mutex::default(),
+                resolving: Mutex::default(),
+                cycle: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The MiniJinja context resolving these variables.
+    pub fn context(&self) -> minijinja::Value {
+        minijinja::Value::from_dyn_object(self.inner.clone())
+    }
+
+    /// The rendered value of `name`, if defined.
+    pub fn get(&self, name: &str) -> eyre::Result<Option<Value>> {
+        let value = self.inner.resolve(name);
+        self.explain(value)?
+            .map(|value| serde_yaml::to_value(value).wrap_err("failed to convert variable"))
+            .transpose()
+    }
+
+    /// Recursively walk a `serde_yaml::Value` tree and render any string
+    /// values through minijinja. Non-string values pass through unchanged.
+    pub fn render_value(&self, value: &Value) -> eyre::Result<Value> {
+        let rendered = render_value_inner(&self.inner.engine, value, &self.context());
+        self.explain(rendered)
+    }
+
+    /// Render a single string template.
+    pub fn render_str(&self, template: &str) -> eyre::Result<String> {
+        let rendered = render_string(&self.inner.engine, template, &self.context());
+        self.explain(rendered)
+    }
+
+    /// Whether every condition of a `when:` holds. Conditions are bare
+    /// expressions, without `{{ }}`.
+    pub fn evaluate_when(&self, conditions: &[String]) -> eyre::Result<bool> {
+        let context = self.context();
+        for condition in conditions {
+            let holds = self
+                .inner
+                .engine
+                .evaluate(condition, &context)
+                .wrap_err_with(|| format!("failed to evaluate condition: {condition}"));
+            if !self.explain(holds)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Render a named template with `env`, an [`Engine::environment`] that
+    /// may carry its own loader and syntax.
+    pub fn render_named(
+        &self,
+        env: &minijinja::Environment,
+        name: &str,
+        source: &str,
+    ) -> eyre::Result<String> {
+        let rendered = env
+            .render_named_str(name, source, self.context())
+            .wrap_err_with(|| format!("failed to render template {name}"));
+        self.explain(rendered)
+    }
+
+    /// Replace the nested render errors of a variable cycle by the cycle.
+    fn explain<T, E: Into<eyre::Report>>(&self, result: Result<T, E>) -> eyre::Result<T> {
+        result.map_err(|err| match self.inner.cycle.lock().unwrap().take() {
+            Some(cycle) => eyre!("variable cycle detected: {cycle}"),
+            None => err.into(),
+        })
+    }
+}
+
+impl LazyVars {
+    fn resolve(self: &Arc<Self>, name: &str) -> Result<Option<minijinja::Value>, minijinja::Error> {
+        if let Some(value) = self.resolved.lock().unwrap().get(name) {
+            return Ok(Some(value.clone()));
+        }
+        let Some(raw) = self.raw.get(name) else {
+            return Ok(None);
+        };
+
+        {
+            let mut resolving = self.resolving.lock().unwrap();
+            if let Some(start) = resolving.iter().position(|entry| entry == name) {
+                let mut path = resolving[start..].to_vec();
+                path.push(name.to_owned());
+                let cycle = path.join(" -> ");
+                self.cycle
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(|| cycle.clone());
+                return Err(minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("variable cycle detected: {cycle}"),
+                ));
+            }
+            resolving.push(name.to_owned());
+        }
+
+        trace!(name, "resolving variable");
+        let context = minijinja::Value::from_dyn_object(self.clone());
+        let rendered = render_value_inner(&self.engine, raw, &context);
+        self.resolving.lock().unwrap().pop();
+
+        let value = minijinja::Value::from_serialize(rendered.map_err(|err| {
+            minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("failed to resolve variable '{name}': {err:#}"),
+            )
+        })?);
+        self.resolved
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), value.clone());
+        Ok(Some(value))
+    }
+}
+
+impl Object for LazyVars {
+    fn get_value(self: &Arc<Self>, key: &minijinja::Value) -> Option<minijinja::Value> {
+        match self.resolve(key.as_str()?) {
+            Ok(value) => value,
+            // Surfaces as an error wherever the template uses the value
+            Err(err) => Some(minijinja::Value::from(err)),
+        }
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        let mut names: Vec<&String> = self.raw.keys().collect();
+        names.sort();
+        Enumerator::Values(names.into_iter().map(minijinja::Value::from).collect())
+    }
+}
+
+/// Match a name against a shell wildcard (`*`, `?`, `[...]`).
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex::Regex::new(&regex).is_ok_and(|regex| regex.is_match(name))
+}
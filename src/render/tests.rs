@@ -0,0 +1,204 @@
+//! Ansible's built-in Jinja tests on top of MiniJinja's own set.
+
+use std::{cmp::Ordering, path::Path};
+
+use minijinja::{
+    Environment, Error,
+    value::{Kwargs, Value, ValueKind},
+};
+
+use super::filters::{build_regex, invalid};
+
+pub(super) fn register(env: &mut Environment<'_>) {
+    // Task results
+    env.add_test("changed", changed);
+    env.add_test("change", changed);
+    env.add_test("failed", failed);
+    env.add_test("failure", failed);
+    env.add_test("succeeded", succeeded);
+    env.add_test("success", succeeded);
+    env.add_test("successful", succeeded);
+    env.add_test("skipped", skipped);
+    env.add_test("skip", skipped);
+
+    // Strings
+    env.add_test("match", is_match);
+    env.add_test("search", search);
+    env.add_test("regex", regex);
+    env.add_test("version", version);
+    env.add_test("version_compare", version);
+
+    // Collections
+    env.add_test("subset", subset);
+    env.add_test("superset", superset);
+    env.add_test("contains", contains);
+    env.add_test("any", any);
+    env.add_test("all", all);
+
+    // Truthiness
+    env.add_test("truthy", truthy);
+    env.add_test("falsy", falsy);
+
+    // Controller paths
+    env.add_test("abs", |path: &str| Path::new(path).is_absolute());
+    env.add_test("exists", |path: &str| Path::new(path).exists());
+    env.add_test("file", |path: &str| Path::new(path).is_file());
+    env.add_test("directory", |path: &str| Path::new(path).is_dir());
+    env.add_test("link", |path: &str| Path::new(path).is_symlink());
+}
+
+/// Whether a registered result has `key` set to a true value.
+fn result_flag(value: &Value, key: &str) -> Result<bool, Error> {
+    if value.kind() != ValueKind::Map {
+        return Err(invalid(format!(
+            "the '{key}' test expects a task result dictionary, got {}",
+            value.kind()
+        )));
+    }
+    Ok(value.get_item(&Value::from(key))?.is_true())
+}
+
+fn changed(value: Value) -> Result<bool, Error> {
+    result_flag(&value, "changed")
+}
+
+fn failed(value: Value) -> Result<bool, Error> {
+    result_flag(&value, "failed")
+}
+
+fn succeeded(value: Value) -> Result<bool, Error> {
+    result_flag(&value, "failed").map(|failed| !failed)
+}
+
+fn skipped(value: Value) -> Result<bool, Error> {
+    result_flag(&value, "skipped")
+}
+
+/// `match` is anchored at the start of the string, like Python's `re.match`.
+fn is_match(value: &str, pattern: &str, kwargs: Kwargs) -> Result<bool, Error> {
+    let regex = build_regex(&format!(r"\A(?:{pattern})"), &kwargs)?;
+    kwargs.assert_all_used()?;
+    Ok(regex.is_match(value))
+}
+
+fn search(value: &str, pattern: &str, kwargs: Kwargs) -> Result<bool, Error> {
+    let regex = build_regex(pattern, &kwargs)?;
+    kwargs.assert_all_used()?;
+    Ok(regex.is_match(value))
+}
+
+/// `regex(pattern, match_type='search')` where match_type is `search`,
+/// `match` or `fullmatch`.
+fn regex(value: &str, pattern: &str, kwargs: Kwargs) -> Result<bool, Error> {
+    let match_type: Option<&str> = kwargs.get("match_type")?;
+    match match_type.unwrap_or("search") {
+        "search" => search(value, pattern, kwargs),
+        "match" => is_match(value, pattern, kwargs),
+        "fullmatch" => {
+            let regex = build_regex(&format!(r"\A(?:{pattern})\z"), &kwargs)?;
+            kwargs.assert_all_used()?;
+            Ok(regex.is_match(value))
+        }
+        other => Err(invalid(format!("invalid match_type '{other}'"))),
+    }
+}
+
+/// A component of a loosely parsed version, like Python's `LooseVersion`.
+#[derive(Debug, PartialEq, Eq)]
+enum Component {
+    Number(u64),
+    Text(String),
+}
+
+impl PartialOrd for Component {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Component {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+fn parse_version(version: &str) -> Vec<Component> {
+    let mut components = Vec::new();
+    let mut chars = version.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            components.push(Component::Number(digits.parse().unwrap_or(u64::MAX)));
+        } else if c.is_ascii_alphabetic() {
+            let mut text = String::new();
+            while let Some(letter) = chars.next_if(char::is_ascii_alphabetic) {
+                text.push(letter);
+            }
+            components.push(Component::Text(text));
+        } else {
+            chars.next();
+        }
+    }
+    components
+}
+
+/// `version(other, operator='eq')` comparing loosely parsed versions.
+fn version(
+    value: Value,
+    other: Value,
+    operator: Option<&str>,
+    kwargs: Kwargs,
+) -> Result<bool, Error> {
+    let operator = kwargs.get::<Option<&str>>("operator")?.or(operator);
+    let _: Option<bool> = kwargs.get("strict")?;
+    let _: Option<&str> = kwargs.get("version_type")?;
+    kwargs.assert_all_used()?;
+
+    let ordering = parse_version(&value.to_string()).cmp(&parse_version(&other.to_string()));
+    Ok(match operator.unwrap_or("eq") {
+        "==" | "=" | "eq" => ordering.is_eq(),
+        "!=" | "<>" | "ne" => ordering.is_ne(),
+        "<" | "lt" => ordering.is_lt(),
+        "<=" | "le" => ordering.is_le(),
+        ">" | "gt" => ordering.is_gt(),
+        ">=" | "ge" => ordering.is_ge(),
+        other => return Err(invalid(format!("invalid version operator '{other}'"))),
+    })
+}
+
+fn subset(value: Value, other: Value) -> Result<bool, Error> {
+    let other: Vec<Value> = other.try_iter()?.collect();
+    Ok(value.try_iter()?.all(|item| other.contains(&item)))
+}
+
+fn superset(value: Value, other: Value) -> Result<bool, Error> {
+    subset(other, value)
+}
+
+fn contains(value: Value, item: Value) -> Result<bool, Error> {
+    Ok(value.try_iter()?.any(|candidate| candidate == item))
+}
+
+fn any(value: Value) -> Result<bool, Error> {
+    Ok(value.try_iter()?.any(|item| item.is_true()))
+}
+
+fn all(value: Value) -> Result<bool, Error> {
+    Ok(value.try_iter()?.all(|item| item.is_true()))
+}
+
+fn truthy(value: Value) -> bool {
+    value.is_true()
+}
+
+fn falsy(value: Value) -> bool {
+    !value.is_true()
+}
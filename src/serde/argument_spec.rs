@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// Contents of a role's `meta/argument_specs.yml`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoleArgumentSpecs {
+    #[serde(default)]
+    pub argument_specs: HashMap<String, ArgumentSpec>,
+}
+
+/// Argument specification of a single role entry point (e.g. `main`).
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArgumentSpec {
+    pub short_description: Option<String>,
+    #[serde(default)]
+    pub options: BTreeMap<String, OptionSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OptionSpec {
+    #[serde(default, rename = "type")]
+    pub kind: OptionType,
+    #[serde(default)]
+    pub required: bool,
+    pub default: Option<Value>,
+    pub choices: Option<Vec<Value>>,
+    pub elements: Option<OptionType>,
+    #[serde(default)]
+    pub options: BTreeMap<String, OptionSpec>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionType {
+    #[default]
+    Str,
+    Int,
+    Float,
+    Bool,
+    List,
+    Dict,
+    Path,
+    Raw,
+    Bytes,
+    Bits,
+    Json,
+    Jsonarg,
+}
+
+impl OptionType {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Str => "str",
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::List => "list",
+            Self::Dict => "dict",
+            Self::Path => "path",
+            Self::Raw => "raw",
+            Self::Bytes => "bytes",
+            Self::Bits => "bits",
+            Self::Json => "json",
+            Self::Jsonarg => "jsonarg",
+        }
+    }
+
+    /// Whether `value` is of this type or can be converted to it the way
+    /// Ansible's argument spec validation would.
+    fn accepts(self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::Raw, _) => true,
+            (Self::Str | Self::Path | Self::Bytes | Self::Bits, value) => {
+                matches!(value, Value::String(_) | Value::Number(_) | Value::Bool(_))
+            }
+            (Self::Json | Self::Jsonarg, value) => !matches!(value, Value::Tagged(_)),
+            (Self::Int, Value::Number(number)) => number.is_i64() || number.is_u64(),
+            (Self::Int, Value::String(s)) => s.trim().parse::<i64>().is_ok(),
+            (Self::Float, Value::Number(_)) => true,
+            (Self::Float, Value::String(s)) => s.trim().parse::<f64>().is_ok(),
+            (Self::Bool, Value::Bool(_)) => true,
+            (Self::Bool, Value::Number(number)) => {
+                number.as_i64().is_some_and(|n| n == 0 || n == 1)
+            }
+            (Self::Bool, Value::String(s)) => matches!(
+                s.to_ascii_lowercase().as_str(),
+                "yes" | "no" | "true" | "false" | "on" | "off" | "y" | "n" | "t" | "f" | "1" | "0"
+            ),
+            (Self::List, value) => matches!(
+                value,
+                Value::Sequence(_) | Value::String(_) | Value::Number(_)
+            ),
+            (Self::Dict, Value::Mapping(_)) => true,
+            (Self::Dict, Value::String(s)) => {
+                serde_yaml::from_str::<serde_yaml::Mapping>(s).is_ok()
+                    || s.split([',', ' '])
+                        .filter(|pair| !pair.is_empty())
+                        .all(|pair| pair.contains('='))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl ArgumentSpec {
+    /// Validate `vars` against this spec. Returns one message per invalid or
+    /// missing parameter; an empty list means the input is valid.
+    pub fn validate(&self, vars: &HashMap<String, Value>) -> Vec<String> {
+        let mut errors = Vec::new();
+        validate_options(&self.options, &|key| vars.get(key), "", &mut errors);
+        errors
+    }
+}
+
+fn validate_options<'a>(
+    options: &BTreeMap<String, OptionSpec>,
+    get: &dyn Fn(&str) -> Option<&'a Value>,
+    prefix: &str,
+    errors: &mut Vec<String>,
+) {
+    let missing: Vec<String> = options
+        .iter()
+        .filter(|(name, spec)| spec.required && get(name).is_none())
+        .map(|(name, _)| format!("{prefix}{name}"))
+        .collect();
+    if !missing.is_empty() {
+        errors.push(format!(
+            "missing required arguments: {}",
+            missing.join(", ")
+        ));
+    }
+
+    for (name, spec) in options {
+        let Some(value) = get(name).or(spec.default.as_ref()) else {
+            continue;
+        };
+
+        validate_option(&format!("{prefix}{name}"), spec, value, errors);
+    }
+}
+
+fn validate_option(path: &str, spec: &OptionSpec, value: &Value, errors: &mut Vec<String>) {
+    if value.is_null() {
+        return;
+    }
+
+    if !spec.kind.accepts(value) {
+        errors.push(format!(
+            "argument '{path}' is of type {} and we were unable to convert to {}",
+            value_type_name(value),
+            spec.kind.name()
+        ));
+        return;
+    }
+
+    if let Some(choices) = &spec.choices {
+        let items = match (spec.kind, value) {
+            (OptionType::List, Value::Sequence(items)) => items.iter().collect(),
+            _ => vec![value],
+        };
+        for item in items {
+            if !choices.iter().any(|choice| value_matches(choice, item)) {
+                errors.push(format!(
+                    "value of {path} must be one of: {}, got: {}",
+                    choices
+                        .iter()
+                        .map(value_to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    value_to_string(item)
+                ));
+            }
+        }
+    }
+
+    match (spec.kind, value) {
+        (OptionType::List, Value::Sequence(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                let item_path = format!("{path}[{index}]");
+                if let Some(elements) = spec.elements
+                    && !item.is_null()
+                    && !elements.accepts(item)
+                {
+                    errors.push(format!(
+                        "elements of argument '{path}' are of type {} and we were unable to convert to {}",
+                        value_type_name(item),
+                        elements.name()
+                    ));
+                    continue;
+                }
+                if let (Some(OptionType::Dict), Value::Mapping(map)) = (spec.elements, item) {
+                    validate_suboptions(&item_path, &spec.options, map, errors);
+                }
+            }
+        }
+        (OptionType::Dict, Value::Mapping(map)) if !spec.options.is_empty() => {
+            validate_suboptions(path, &spec.options, map, errors);
+        }
+        _ => {}
+    }
+}
+
+fn validate_suboptions(
+    path: &str,
+    options: &BTreeMap<String, OptionSpec>,
+    map: &serde_yaml::Mapping,
+    errors: &mut Vec<String>,
+) {
+    if options.is_empty() {
+        return;
+    }
+
+    let unsupported: Vec<String> = map
+        .keys()
+        .map(value_to_string)
+        .filter(|key| !options.contains_key(key))
+        .collect();
+    if !unsupported.is_empty() {
+        errors.push(format!(
+            "unsupported parameters for {path}: {}",
+            unsupported.join(", ")
+        ));
+    }
+
+    validate_options(options, &|key| map.get(key), &format!("{path}."), errors);
+}
+
+fn value_matches(choice: &Value, value: &Value) -> bool {
+    choice == value || value_to_string(choice) == value_to_string(value)
+}
+
+fn value_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(number) if number.is_f64() => "float",
+        Value::Number(_) => "int",
+        Value::String(_) => "str",
+        Value::Sequence(_) => "list",
+        Value::Mapping(_) => "dict",
+        Value::Tagged(_) => "tagged",
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Null => "null".to_string(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_end().to_owned())
+            .unwrap_or_default(),
+    }
+}
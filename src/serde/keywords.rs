@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use eyre::Context;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_yaml::Value;
+
+use crate::render::Vars;
+
+/// Keywords of a task that can also be set on its role or play, which the
+/// task inherits unless it sets them itself.
+pub const TASK_KEYWORDS: &[&str] = &[
+    "become",
+    "become_user",
+    "become_method",
+    "become_flags",
+    "environment",
+    "ignore_errors",
+    "check_mode",
+    "timeout",
+    "remote_user",
+];
+
+/// A keyword value, or a template rendered with the task's variables when
+/// the keyword is applied.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Templated<T> {
+    Value(T),
+    Template(String),
+}
+
+impl<T: Clone + DeserializeOwned> Templated<T> {
+    pub fn resolve(&self, vars: &Vars) -> eyre::Result<T> {
+        match self {
+            Self::Value(value) => Ok(value.clone()),
+            Self::Template(template) => {
+                let rendered = vars.render_str(template)?;
+                serde_yaml::from_str(&rendered).wrap_err_with(|| {
+                    format!("invalid keyword value '{rendered}' from '{template}'")
+                })
+            }
+        }
+    }
+}
+
+/// Keywords set on a play, a role entry, a task or a handler.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Keywords {
+    pub r#become: Option<Templated<bool>>,
+    pub become_user: Option<String>,
+    pub become_method: Option<String>,
+    pub become_flags: Option<String>,
+    pub environment: Option<HashMap<String, Value>>,
+    pub ignore_errors: Option<Templated<bool>>,
+    pub check_mode: Option<Templated<bool>>,
+    /// Seconds a task may run, 0 for no limit
+    pub timeout: Option<Templated<u64>>,
+    /// SSH user, unless the inventory sets `ansible_user`
+    pub remote_user: Option<String>,
+    /// Only valid on plays and roles
+    pub collections: Option<Vec<String>>,
+}
+
+impl Keywords {
+    /// These keywords within `parent`: values set here win, and
+    /// `environment` variables are added to the parent's.
+    pub fn inherit(&self, parent: &Keywords) -> Keywords {
+        let environment = match (&parent.environment, &self.environment) {
+            (Some(parent), Some(own)) => {
+                let mut merged = parent.clone();
+                merged.extend(own.clone());
+                Some(merged)
+            }
+            (parent, own) => own.clone().or_else(|| parent.clone()),
+        };
+
+        Keywords {
+            r#become: self.r#become.clone().or(parent.r#become.clone()),
+            become_user: self.become_user.clone().or(parent.become_user.clone()),
+            become_method: self.become_method.clone().or(parent.become_method.clone()),
+            become_flags: self.become_flags.clone().or(parent.become_flags.clone()),
+            environment,
+            ignore_errors: self.ignore_errors.clone().or(parent.ignore_errors.clone()),
+            check_mode: self.check_mode.clone().or(parent.check_mode.clone()),
+            timeout: self.timeout.clone().or(parent.timeout.clone()),
+            remote_user: self.remote_user.clone().or(parent.remote_user.clone()),
+            collections: self.collections.clone().or(parent.collections.clone()),
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod argument_spec;
+pub mod keywords;
 pub mod play;
 pub mod task;
@@ -3,18 +3,38 @@ use std::collections::HashMap;
 use serde::Deserialize;
 use serde_yaml::Value;
 
-use super::task::TaskDescription;
+use super::{
+    keywords::Keywords,
+    task::{HandlerDescription, TaskDescription},
+};
+use crate::task::setup::GatherSubset;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Play {
     pub name: Option<String>,
     pub hosts: String,
-    pub remote_user: Option<String>,
+    #[serde(flatten)]
+    pub keywords: Keywords,
+    #[serde(default = "default_gather_facts")]
+    pub gather_facts: bool,
+    pub gather_subset: Option<GatherSubset>,
+    /// Run notified handlers even when a task fails; defaults to
+    /// `--force-handlers`
+    pub force_handlers: Option<bool>,
+
+    pub vars: Option<HashMap<String, Value>>,
+    pub vars_files: Option<Vec<VarsFile>>,
+    pub vars_prompt: Option<Vec<VarsPrompt>>,
 
     pub pre_tasks: Option<Vec<TaskDescription>>,
     pub roles: Option<Vec<PlayRole>>,
     pub tasks: Option<Vec<TaskDescription>>,
     pub post_tasks: Option<Vec<TaskDescription>>,
+    pub handlers: Option<Vec<HandlerDescription>>,
+}
+
+fn default_gather_facts() -> bool {
+    true
 }
 
 impl Play {
@@ -23,6 +43,30 @@ impl Play {
     }
 }
 
+/// An entry of `vars_files:`: a single path, or a list of alternatives of
+/// which the first existing file is loaded.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum VarsFile {
+    Path(String),
+    Alternatives(Vec<String>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VarsPrompt {
+    pub name: String,
+    pub prompt: Option<String>,
+    #[serde(default = "default_private")]
+    pub private: bool,
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+fn default_private() -> bool {
+    true
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
 pub enum PlayRole {
@@ -30,6 +74,8 @@ pub enum PlayRole {
     Role {
         role: String,
         vars: Option<HashMap<String, Value>>,
+        #[serde(flatten)]
+        keywords: Box<Keywords>,
     },
 }
 
@@ -41,6 +87,13 @@ impl PlayRole {
         }
     }
 
+    pub fn keywords(&self) -> Keywords {
+        match self {
+            Self::RoleName(_) => Keywords::default(),
+            Self::Role { keywords, .. } => keywords.as_ref().clone(),
+        }
+    }
+
     pub fn vars(&self) -> Option<&HashMap<String, Value>> {
         match self {
             Self::RoleName(_) => None,
@@ -5,22 +5,28 @@ use serde::{Deserialize, Deserializer};
 use serde_yaml::Value;
 use tracing::debug;
 
+use super::keywords::{Keywords, TASK_KEYWORDS};
 use crate::{known_tasks, task::TaskId};
 
+/// Modules whose command can run as an `async` job.
+const ASYNC_TASKS: &[&str] = &["ansible.builtin.shell"];
+
 #[derive(Clone, Debug)]
 pub struct TaskDescription {
     pub name: Option<String>,
     pub task_id: TaskId,
     pub args: Value,
-    pub r#become: bool,
-    pub become_user: Option<String>,
+    pub keywords: Keywords,
     pub delegate_to: Option<String>,
-    pub ignore_errors: bool,
 
     pub when: Vec<String>,
     pub notify: Vec<String>,
     pub register: Option<String>,
     pub vars: Option<HashMap<String, Value>>,
+    /// Seconds the task may run in the background, as a transient unit
+    pub r#async: Option<u64>,
+    /// Seconds between checks on an `async` task, 0 to not wait for it
+    pub poll: Option<u64>,
 }
 
 impl<'de> Deserialize<'de> for TaskDescription {
@@ -48,16 +54,21 @@ pub struct HandlerDescription {
     pub name: Option<String>,
     pub task_id: TaskId,
     pub args: Value,
-    pub r#become: bool,
-    pub become_user: Option<String>,
+    /// Also inherits the keywords of its play or role during
+    /// `register_handlers`
+    pub keywords: Keywords,
 
     pub when: Vec<String>,
-    pub listen: Option<String>,
+    pub notify: Vec<String>,
+    pub listen: Vec<String>,
     pub vars: Option<HashMap<String, Value>>,
 
     /// Resource directory context from the role this handler belongs to.
     /// Set during `register_handlers`, not during deserialization.
     pub role_resource_dir: Option<PathBuf>,
+    /// Names notifying only this handler: its name, also prefixed with its
+    /// role's name. Set during `register_handlers`.
+    pub names: Vec<String>,
 }
 
 impl<'de> Deserialize<'de> for HandlerDescription {
@@ -80,6 +91,18 @@ impl<'de> Deserialize<'de> for HandlerDescription {
     }
 }
 
+/// A keyword taking a single string or a list of strings.
+fn string_or_list<E: serde::de::Error>(key: &str, value: Value) -> Result<Vec<String>, E> {
+    match value {
+        Value::String(value) => Ok(vec![value]),
+        value @ Value::Sequence(_) => serde_yaml::from_value(value)
+            .map_err(|_| E::custom(format!("expected {key} to be a list of strings"))),
+        _ => Err(E::custom(format!(
+            "expected {key} to be a list of strings, or a string"
+        ))),
+    }
+}
+
 enum TaskOrHandler {
     Task(TaskDescription),
     Handler(HandlerDescription),
@@ -127,15 +150,15 @@ impl<'de> serde::de::Visitor<'de> for TaskVisitor {
         let mut name = None::<String>;
         let mut task_id = None::<TaskId>;
         let mut args = None::<Value>;
-        let mut r#become = None::<bool>;
-        let mut become_user = None::<String>;
+        let mut keywords = serde_yaml::Mapping::new();
         let mut delegate_to = None::<String>;
         let mut when = None::<Vec<String>>;
         let mut notify = None::<Vec<String>>;
         let mut register = None::<String>;
         let mut vars = None::<HashMap<String, Value>>;
-        let mut ignore_errors = None::<bool>;
-        let mut listen = None::<String>;
+        let mut listen = None::<Vec<String>>;
+        let mut r#async = None::<u64>;
+        let mut poll = None::<u64>;
 
         while let Some((key, value)) = map.next_entry::<String, Value>()? {
             match key.as_str() {
@@ -163,87 +186,23 @@ impl<'de> serde::de::Visitor<'de> for TaskVisitor {
                         return Err(serde::de::Error::custom("duplicate delegate_to"));
                     }
                 }
-                "become" => {
-                    if r#become.is_none() {
-                        r#become = Some(
-                            value
-                                .as_bool()
-                                .ok_or(serde::de::Error::custom("name is not a boolean"))?,
-                        );
-                    } else {
-                        return Err(serde::de::Error::custom("duplicate become"));
-                    }
-                }
-                "become_user" => {
-                    if become_user.is_none() {
-                        become_user = Some(
-                            value
-                                .as_str()
-                                .map(str::to_owned)
-                                .ok_or(serde::de::Error::custom("become_user is not a string"))?,
-                        );
-                    } else {
-                        return Err(serde::de::Error::custom("duplicate become_user"));
-                    }
-                }
-                "ignore_errors" => {
-                    if ignore_errors.is_none() {
-                        ignore_errors =
-                            Some(value.as_bool().ok_or(serde::de::Error::custom(
-                                "ignore_errors is not a boolean",
-                            ))?);
-                    } else {
-                        return Err(serde::de::Error::custom("duplicate ignore_errors"));
-                    }
-                }
                 "when" => {
                     if when.is_none() {
-                        if let Some(expr) = value.as_str() {
-                            when = Some(vec![expr.to_owned()]);
-                        } else if value.as_sequence().is_some() {
-                            let parsed = match serde_yaml::from_value(value) {
-                                Err(_) => {
-                                    return Err(serde::de::Error::custom(
-                                        "expected when to be a list of strings",
-                                    ));
-                                }
-                                Ok(v) => v,
-                            };
-
-                            when = Some(parsed);
-                        } else {
-                            return Err(serde::de::Error::custom(
-                                "expected when to be a list of strings, or a string",
-                            ));
-                        }
+                        when = Some(string_or_list("when", value)?);
                     } else {
                         return Err(serde::de::Error::custom("duplicate when"));
                     }
                 }
                 "listen" if self.expect_handler => {
                     if listen.is_none() {
-                        listen = Some(
-                            value
-                                .as_str()
-                                .map(str::to_owned)
-                                .ok_or(serde::de::Error::custom("listen is not a string"))?,
-                        );
+                        listen = Some(string_or_list("listen", value)?);
                     } else {
                         return Err(serde::de::Error::custom("duplicate listen"));
                     }
                 }
-                "notify" if !self.expect_handler => {
+                "notify" => {
                     if notify.is_none() {
-                        let parsed = match serde_yaml::from_value(value) {
-                            Err(_) => {
-                                return Err(serde::de::Error::custom(
-                                    "expected notify to be a list of strings",
-                                ));
-                            }
-                            Ok(v) => v,
-                        };
-
-                        notify = Some(parsed);
+                        notify = Some(string_or_list("notify", value)?);
                     } else {
                         return Err(serde::de::Error::custom("duplicate notify"));
                     }
@@ -270,6 +229,29 @@ impl<'de> serde::de::Visitor<'de> for TaskVisitor {
                         return Err(serde::de::Error::custom("duplicate vars"));
                     }
                 }
+                "async" if !self.expect_handler => {
+                    if r#async.is_none() {
+                        r#async =
+                            Some(value.as_u64().ok_or(serde::de::Error::custom(
+                                "async is not a number of seconds",
+                            ))?);
+                    } else {
+                        return Err(serde::de::Error::custom("duplicate async"));
+                    }
+                }
+                "poll" if !self.expect_handler => {
+                    if poll.is_none() {
+                        poll =
+                            Some(value.as_u64().ok_or(serde::de::Error::custom(
+                                "poll is not a number of seconds",
+                            ))?);
+                    } else {
+                        return Err(serde::de::Error::custom("duplicate poll"));
+                    }
+                }
+                key if TASK_KEYWORDS.contains(&key) => {
+                    keywords.insert(key.into(), value);
+                }
                 key => {
                     if let Some(task) = known_tasks().get(key) {
                         if task_id.is_none() {
@@ -285,7 +267,20 @@ impl<'de> serde::de::Visitor<'de> for TaskVisitor {
             }
         }
 
-        let _ = listen;
+        let keywords = Keywords::deserialize(Value::Mapping(keywords))
+            .map_err(|err| serde::de::Error::custom(format!("invalid keyword: {err}")))?;
+
+        // A job is a single detached command, so only modules running one
+        // command can be `async`
+        if let (Some(_), Some(task_id)) = (r#async, &task_id)
+            && !ASYNC_TASKS.contains(&task_id.name())
+        {
+            return Err(serde::de::Error::custom(format!(
+                "async is not supported by {}",
+                task_id.name()
+            )));
+        }
+
         Ok(if self.expect_handler {
             if name.is_none() && listen.is_none() {
                 return Err(serde::de::Error::custom(
@@ -297,26 +292,27 @@ impl<'de> serde::de::Visitor<'de> for TaskVisitor {
                 name,
                 task_id: task_id.unwrap(),
                 args: args.unwrap(),
-                r#become: r#become.unwrap_or_default(),
-                become_user,
+                keywords,
                 when: when.unwrap_or_default(),
-                listen,
+                notify: notify.unwrap_or_default(),
+                listen: listen.unwrap_or_default(),
                 vars,
                 role_resource_dir: None,
+                names: Vec::new(),
             })
         } else {
             TaskOrHandler::Task(TaskDescription {
                 name,
                 task_id: task_id.unwrap(),
                 args: args.unwrap(),
-                r#become: r#become.unwrap_or_default(),
-                become_user,
+                keywords,
                 delegate_to,
-                ignore_errors: ignore_errors.unwrap_or_default(),
                 when: when.unwrap_or_default(),
                 notify: notify.unwrap_or_default(),
                 register,
                 vars,
+                r#async,
+                poll,
             })
         })
     }
@@ -0,0 +1,393 @@
+//! Decryption of SOPS-encrypted YAML/JSON documents whose data key is
+//! protected with age.
+//!
+//! Every encrypted leaf is `ENC[AES256_GCM,data:…,iv:…,tag:…,type:…]`,
+//! sealed with the data key (32-byte nonce) and its key path joined with
+//! `:` as additional data. The document MAC is the SHA-512 of all
+//! plaintext values in document order, encrypted the same way with the
+//! `lastmodified` timestamp as additional data.
+
+use std::{
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use aes_gcm::{
+    AesGcm, KeyInit,
+    aead::{Aead, Payload, consts::U32},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use eyre::{Context, eyre};
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha512};
+
+type Aes256Gcm32 = AesGcm<aes::Aes256, U32>;
+
+const METADATA_KEY: &str = "sops";
+const DEFAULT_UNENCRYPTED_SUFFIX: &str = "_unencrypted";
+
+static AGE_KEY_FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// Register the age identity files used to decrypt SOPS data keys. When none
+/// are given, `sops/age/keys.txt` in the user's config directory is used.
+/// Keys in `SOPS_AGE_KEY` are always tried. May only be called once.
+pub fn set_age_key_files(files: Vec<PathBuf>) -> eyre::Result<()> {
+    AGE_KEY_FILES
+        .set(files)
+        .map_err(|_| eyre!("age key files were already configured"))
+}
+
+/// Whether `data` may be a SOPS document, i.e. has a top-level `sops` key.
+/// This is a cheap check; [`decrypt`] leaves documents whose `sops` key is
+/// not SOPS metadata unchanged.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.split(|byte| *byte == b'\n')
+        .any(|line| line.starts_with(b"sops:") || line.trim_ascii_start().starts_with(b"\"sops\":"))
+}
+
+/// Whether `value` is SOPS metadata: a mapping with a `mac` and either
+/// `lastmodified` or `age` recipients, rather than a plain `sops` variable.
+fn is_metadata(value: &Value) -> bool {
+    let Value::Mapping(metadata) = value else {
+        return false;
+    };
+    matches!(metadata.get("mac"), Some(Value::String(_)))
+        && (matches!(metadata.get("lastmodified"), Some(Value::String(_)))
+            || matches!(metadata.get("age"), Some(Value::Sequence(_))))
+}
+
+/// Decrypt a SOPS document in memory and verify its MAC. Documents without
+/// SOPS metadata are returned unchanged.
+pub fn decrypt(document: Value) -> eyre::Result<Value> {
+    let Value::Mapping(mut tree) = document else {
+        return Ok(document);
+    };
+    if !tree.get(METADATA_KEY).is_some_and(is_metadata) {
+        return Ok(Value::Mapping(tree));
+    }
+    let metadata = tree.remove(METADATA_KEY).unwrap();
+    let metadata: Metadata = serde_yaml::from_value(metadata).wrap_err("invalid sops metadata")?;
+
+    let key = metadata.data_key()?;
+    let rules = metadata.rules()?;
+
+    let mut hasher = Sha512::new();
+    let mut path = Vec::new();
+    let tree = decrypt_mapping(tree, &key, &rules, &mut path, &mut hasher)?;
+    let computed = hex::encode_upper(hasher.finalize());
+
+    let mac = decrypt_leaf(&metadata.mac, &key, &metadata.lastmodified)
+        .wrap_err("failed to decrypt sops MAC")?;
+    if mac.as_str() != Some(computed.as_str()) {
+        return Err(eyre!(
+            "sops MAC mismatch: the file has been modified without re-encryption"
+        ));
+    }
+
+    Ok(Value::Mapping(tree))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    age: Vec<AgeRecipient>,
+    lastmodified: String,
+    mac: String,
+    unencrypted_suffix: Option<String>,
+    encrypted_suffix: Option<String>,
+    unencrypted_regex: Option<String>,
+    encrypted_regex: Option<String>,
+    #[serde(default)]
+    mac_only_encrypted: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AgeRecipient {
+    recipient: String,
+    enc: String,
+}
+
+/// Which leaves of the tree are encrypted, derived from the key path.
+struct Rules {
+    unencrypted_suffix: Option<String>,
+    encrypted_suffix: Option<String>,
+    unencrypted_regex: Option<Regex>,
+    encrypted_regex: Option<Regex>,
+    mac_only_encrypted: bool,
+}
+
+impl Metadata {
+    fn rules(&self) -> eyre::Result<Rules> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .wrap_err("invalid sops metadata regex")
+        };
+        let any_rule = self.encrypted_suffix.is_some()
+            || self.unencrypted_regex.is_some()
+            || self.encrypted_regex.is_some();
+        Ok(Rules {
+            unencrypted_suffix: self
+                .unencrypted_suffix
+                .clone()
+                .or_else(|| (!any_rule).then(|| DEFAULT_UNENCRYPTED_SUFFIX.to_string())),
+            encrypted_suffix: self.encrypted_suffix.clone(),
+            unencrypted_regex: compile(&self.unencrypted_regex)?,
+            encrypted_regex: compile(&self.encrypted_regex)?,
+            mac_only_encrypted: self.mac_only_encrypted,
+        })
+    }
+
+    /// Recover the data key from the first age stanza one of our identities
+    /// can open.
+    fn data_key(&self) -> eyre::Result<[u8; 32]> {
+        if self.age.is_empty() {
+            return Err(eyre!(
+                "sops file has no age recipients; only age-encrypted files are supported"
+            ));
+        }
+
+        let identities = identities()?;
+        if identities.is_empty() {
+            return Err(eyre!(
+                "encountered a sops file but no age identities were found (use --sops-age-key-file or SOPS_AGE_KEY_FILE)"
+            ));
+        }
+
+        for stanza in &self.age {
+            let Ok(decryptor) =
+                age::Decryptor::new_buffered(age::armor::ArmoredReader::new(stanza.enc.as_bytes()))
+            else {
+                continue;
+            };
+            let Ok(mut reader) = decryptor.decrypt(
+                identities
+                    .iter()
+                    .map(|identity| identity.as_ref() as &dyn age::Identity),
+            ) else {
+                continue;
+            };
+            let mut key = Vec::new();
+            reader
+                .read_to_end(&mut key)
+                .wrap_err_with(|| format!("failed to decrypt data key for {}", stanza.recipient))?;
+            return key
+                .try_into()
+                .map_err(|_| eyre!("sops data key has an invalid length"));
+        }
+
+        Err(eyre!(
+            "no age identity matched the recipients of this sops file: {}",
+            self.age
+                .iter()
+                .map(|stanza| stanza.recipient.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+impl Rules {
+    fn is_encrypted(&self, path: &[String]) -> bool {
+        let mut encrypted = true;
+        if let Some(suffix) = &self.unencrypted_suffix
+            && path.iter().any(|key| key.ends_with(suffix.as_str()))
+        {
+            encrypted = false;
+        }
+        if let Some(suffix) = &self.encrypted_suffix {
+            encrypted = path.iter().any(|key| key.ends_with(suffix.as_str()));
+        }
+        if let Some(regex) = &self.unencrypted_regex
+            && path.iter().any(|key| regex.is_match(key))
+        {
+            encrypted = false;
+        }
+        if let Some(regex) = &self.encrypted_regex {
+            encrypted = path.iter().any(|key| regex.is_match(key));
+        }
+        encrypted
+    }
+}
+
+/// Load age identities from `SOPS_AGE_KEY` and the registered key files,
+/// falling back to the default key file.
+fn identities() -> eyre::Result<Vec<Box<dyn age::Identity>>> {
+    let mut identities = Vec::new();
+
+    if let Ok(keys) = std::env::var("SOPS_AGE_KEY") {
+        identities
+            .extend(parse_identities(keys.as_bytes()).wrap_err("failed to parse SOPS_AGE_KEY")?);
+    }
+
+    let files = AGE_KEY_FILES.get().map(Vec::as_slice).unwrap_or_default();
+    for file in files {
+        identities.extend(load_identity_file(file)?);
+    }
+
+    if files.is_empty()
+        && let Some(file) = default_key_file()
+        && file.exists()
+    {
+        identities.extend(load_identity_file(&file)?);
+    }
+
+    Ok(identities)
+}
+
+fn default_key_file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("sops").join("age").join("keys.txt"))
+}
+
+fn load_identity_file(path: &Path) -> eyre::Result<Vec<Box<dyn age::Identity>>> {
+    let file = std::fs::File::open(path)
+        .wrap_err_with(|| format!("failed to open age key file {path:?}"))?;
+    parse_identities(BufReader::new(file))
+        .wrap_err_with(|| format!("failed to parse age key file {path:?}"))
+}
+
+fn parse_identities(data: impl std::io::BufRead) -> eyre::Result<Vec<Box<dyn age::Identity>>> {
+    Ok(age::IdentityFile::from_buffer(data)?.into_identities()?)
+}
+
+fn decrypt_mapping(
+    mapping: Mapping,
+    key: &[u8; 32],
+    rules: &Rules,
+    path: &mut Vec<String>,
+    hasher: &mut Sha512,
+) -> eyre::Result<Mapping> {
+    let mut decrypted = Mapping::new();
+    for (name, value) in mapping {
+        let Some(segment) = name.as_str() else {
+            return Err(eyre!("sops documents must only have string keys"));
+        };
+        path.push(segment.to_string());
+        let value = decrypt_node(value, key, rules, path, hasher)?;
+        path.pop();
+        decrypted.insert(name, value);
+    }
+    Ok(decrypted)
+}
+
+fn decrypt_node(
+    value: Value,
+    key: &[u8; 32],
+    rules: &Rules,
+    path: &mut Vec<String>,
+    hasher: &mut Sha512,
+) -> eyre::Result<Value> {
+    match value {
+        Value::Mapping(mapping) => Ok(Value::Mapping(decrypt_mapping(
+            mapping, key, rules, path, hasher,
+        )?)),
+        Value::Sequence(items) => items
+            .into_iter()
+            .map(|item| decrypt_node(item, key, rules, path, hasher))
+            .collect::<eyre::Result<_>>()
+            .map(Value::Sequence),
+        leaf => {
+            let encrypted = rules.is_encrypted(path);
+            let plaintext = if encrypted {
+                let Value::String(ciphertext) = &leaf else {
+                    return Err(eyre!("sops value at '{}' is not encrypted", path.join(".")));
+                };
+                let additional_data = format!("{}:", path.join(":"));
+                decrypt_leaf(ciphertext, key, &additional_data)
+                    .wrap_err_with(|| format!("failed to decrypt '{}'", path.join(".")))?
+            } else {
+                leaf
+            };
+            if encrypted || !rules.mac_only_encrypted {
+                hasher.update(mac_bytes(&plaintext));
+            }
+            Ok(plaintext)
+        }
+    }
+}
+
+/// Decrypt a single `ENC[AES256_GCM,…]` value into a typed YAML value.
+fn decrypt_leaf(value: &str, key: &[u8; 32], additional_data: &str) -> eyre::Result<Value> {
+    if value.is_empty() {
+        return Ok(Value::String(String::new()));
+    }
+
+    let fields = value
+        .strip_prefix("ENC[AES256_GCM,")
+        .and_then(|fields| fields.strip_suffix(']'))
+        .ok_or_else(|| eyre!("value is not in the sops ENC[AES256_GCM,...] format"))?;
+    let (mut data, mut iv, mut tag, mut kind) = (None, None, None, None);
+    for field in fields.split(',') {
+        match field.split_once(':') {
+            Some(("data", v)) => data = Some(v),
+            Some(("iv", v)) => iv = Some(v),
+            Some(("tag", v)) => tag = Some(v),
+            Some(("type", v)) => kind = Some(v),
+            _ => {}
+        }
+    }
+    let (Some(data), Some(iv), Some(tag), Some(kind)) = (data, iv, tag, kind) else {
+        return Err(eyre!("sops value is missing data, iv, tag or type"));
+    };
+
+    let mut ciphertext = BASE64
+        .decode(data)
+        .wrap_err("invalid base64 in sops data")?;
+    ciphertext.extend(BASE64.decode(tag).wrap_err("invalid base64 in sops tag")?);
+    let iv = BASE64.decode(iv).wrap_err("invalid base64 in sops iv")?;
+    if iv.len() != 32 {
+        return Err(eyre!("sops iv must be 32 bytes, got {}", iv.len()));
+    }
+
+    let cipher = Aes256Gcm32::new(key.into());
+    let plaintext = cipher
+        .decrypt(
+            iv.as_slice().into(),
+            Payload {
+                msg: &ciphertext,
+                aad: additional_data.as_bytes(),
+            },
+        )
+        .map_err(|_| eyre!("authentication failed (wrong key or tampered value)"))?;
+
+    let text = || String::from_utf8(plaintext.clone()).wrap_err("sops value is not UTF-8");
+    Ok(match kind {
+        "str" | "comment" => Value::String(text()?),
+        "bytes" => Value::String(String::from_utf8_lossy(&plaintext).into_owned()),
+        "int" => Value::Number(text()?.parse::<i64>().wrap_err("invalid sops int")?.into()),
+        "float" => Value::Number(
+            text()?
+                .parse::<f64>()
+                .wrap_err("invalid sops float")?
+                .into(),
+        ),
+        "bool" => Value::Bool(match text()?.as_str() {
+            "1" | "t" | "T" | "TRUE" | "true" | "True" => true,
+            "0" | "f" | "F" | "FALSE" | "false" | "False" => false,
+            other => return Err(eyre!("invalid sops bool '{other}'")),
+        }),
+        other => return Err(eyre!("unsupported sops value type '{other}'")),
+    })
+}
+
+/// Byte representation of a plaintext value as hashed into the SOPS MAC.
+fn mac_bytes(value: &Value) -> Vec<u8> {
+    match value {
+        Value::String(s) => s.as_bytes().to_vec(),
+        Value::Bool(true) => b"True".to_vec(),
+        Value::Bool(false) => b"False".to_vec(),
+        // Go's shortest float formatting prints `1.0` as `1`, like Rust's
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => float.to_string().into_bytes(),
+            _ => number.to_string().into_bytes(),
+        },
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,314 @@
+use std::{collections::HashMap, os::unix::fs::MetadataExt, time::Duration};
+
+use async_trait::async_trait;
+use eyre::eyre;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use structstruck::strike;
+use tracing::{debug, trace};
+
+use crate::{command::CommandTarget, task::KeroseneTaskInfo};
+
+use super::{
+    CommandFailed, CommandOutput, CommandTimedOut, RunCommandOpts, StructuredTask, TaskContext,
+    TaskContextInner, TaskOutput, TaskResult,
+};
+
+/// Prefix of the transient units running `async` tasks.
+const UNIT_PREFIX: &str = "kerosene-job-";
+
+/// How the running task's command is launched with `async`.
+#[derive(Clone, Debug)]
+pub struct AsyncJob {
+    pub id: String,
+    /// Longest the job may run, from `async`
+    pub timeout: Duration,
+    /// Interval between checks from `poll`, zero to not wait for the job
+    pub poll: Duration,
+}
+
+impl AsyncJob {
+    /// Result keys added to the registered value of an `async` task.
+    pub fn result(&self, finished: bool) -> Mapping {
+        Mapping::from_iter([
+            ("ansible_job_id".into(), Value::from(self.id.as_str())),
+            ("started".into(), Value::from(1)),
+            ("finished".into(), Value::from(u8::from(finished))),
+        ])
+    }
+}
+
+fn unit_name(id: &str) -> String {
+    format!("{UNIT_PREFIX}{id}.service")
+}
+
+/// The state of a job's unit.
+enum JobState {
+    Running,
+    Finished {
+        output: CommandOutput,
+        /// The job's `async` limit, if it was reached
+        timed_out: Option<Duration>,
+    },
+}
+
+/// A time span as shown by `systemctl`, such as `1h 2min 3s`.
+fn parse_timespan(span: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    for part in span.split_whitespace() {
+        let split = part.find(|c: char| !c.is_ascii_digit())?;
+        let (value, unit) = part.split_at(split);
+        let value: u64 = value.parse().ok()?;
+        total += match unit {
+            "d" => Duration::from_secs(value * 86400),
+            "h" => Duration::from_secs(value * 3600),
+            "min" => Duration::from_secs(value * 60),
+            "s" => Duration::from_secs(value),
+            "ms" => Duration::from_millis(value),
+            "us" => Duration::from_micros(value),
+            _ => return None,
+        };
+    }
+    Some(total)
+}
+
+/// Whether commands of the running task run as root: escalated to root,
+/// or as a root SSH or local user.
+fn runs_as_root(ctx: &TaskContextInner) -> bool {
+    if let Some(elevate) = &ctx.do_become {
+        return elevate.user == "root";
+    }
+    match &ctx.command_target {
+        CommandTarget::Remote { user, .. } => user.as_deref() == Some("root"),
+        CommandTarget::Local { .. } => {
+            std::fs::metadata("/proc/self").is_ok_and(|meta| meta.uid() == 0)
+        }
+    }
+}
+
+/// Launch `opts` as the transient unit of `job`. Commands feeding stdin
+/// cannot run detached.
+pub(crate) fn launch(
+    ctx: &TaskContextInner,
+    job: &AsyncJob,
+    opts: RunCommandOpts,
+) -> eyre::Result<CommandOutput> {
+    if opts.stdin.is_some() {
+        return Err(eyre!("async is not supported for tasks writing to stdin"));
+    }
+    if !runs_as_root(ctx) {
+        return Err(eyre!(
+            "async needs root on the host to run system units, set 'become: true' or a root 'remote_user'"
+        ));
+    }
+
+    // The unit neither inherits the working directory nor the environment
+    // of `systemd-run`
+    let unit = unit_name(&job.id);
+    let mut command = vec![
+        "systemd-run".to_owned(),
+        format!("--unit={unit}"),
+        "--quiet".to_owned(),
+        "--property=RemainAfterExit=yes".to_owned(),
+        format!("--property=RuntimeMaxSec={}", job.timeout.as_secs().max(1)),
+    ];
+    if let Some(working_directory) = opts.working_directory {
+        command.push(format!("--working-directory={working_directory}"));
+    }
+    for (name, value) in &ctx.environment {
+        command.push(format!("--setenv={name}={value}"));
+    }
+    command.push("--".to_owned());
+    command.extend(opts.command.iter().map(|arg| (*arg).to_owned()));
+
+    debug!(unit, "launching async job");
+    let launched = ctx.run_command_now(RunCommandOpts {
+        command: command.iter().map(String::as_str).collect(),
+        capture: true,
+        ..Default::default()
+    })?;
+    Ok(CommandOutput::new(
+        &opts.command,
+        launched.stdout,
+        launched.stderr,
+        launched.rc,
+    ))
+}
+
+/// Check on the job `id` until it finishes, every `poll`. Its unit is
+/// removed once finished, or when checking on it fails. `RuntimeMaxSec`
+/// bounds the job, so the checks are not bounded by the task's `timeout`.
+pub(crate) fn wait(ctx: &TaskContextInner, job: &AsyncJob) -> eyre::Result<CommandOutput> {
+    let polled = loop {
+        std::thread::sleep(job.poll);
+        match status(ctx, &job.id, true) {
+            Ok(JobState::Running) => trace!(id = job.id, "async job still running"),
+            Ok(JobState::Finished { output, timed_out }) => break Ok((output, timed_out)),
+            Err(err) => break Err(err),
+        }
+    };
+
+    let (output, timed_out) = match polled {
+        Ok(polled) => polled,
+        Err(err) => {
+            // Nothing checks on the job anymore
+            let _ = cleanup(ctx, &job.id, true);
+            return Err(err);
+        }
+    };
+    cleanup(ctx, &job.id, true)?;
+    finished(output, timed_out)
+}
+
+/// The output of a finished job, or the error of one that failed.
+fn finished(output: CommandOutput, timed_out: Option<Duration>) -> eyre::Result<CommandOutput> {
+    if let Some(timeout) = timed_out {
+        return Err(eyre::Report::new(CommandTimedOut { output, timeout }));
+    }
+    if output.rc != 0 {
+        return Err(eyre::Report::new(CommandFailed { output }));
+    }
+    Ok(output)
+}
+
+/// The state of the job `id`. With `untimed`, the task's `timeout` does
+/// not apply.
+fn status(ctx: &TaskContextInner, id: &str, untimed: bool) -> eyre::Result<JobState> {
+    let unit = unit_name(id);
+    let shown = ctx.run_command_now(RunCommandOpts {
+        command: vec![
+            "systemctl",
+            "show",
+            "--property=LoadState,ActiveState,SubState,Result,ExecMainCode,ExecMainStatus,RuntimeMaxUSec,Description",
+            unit.as_str(),
+        ],
+        capture: true,
+        read_only: true,
+        untimed,
+        ..Default::default()
+    })?;
+    let properties: HashMap<&str, &str> = shown
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let property = |name: &str| properties.get(name).copied().unwrap_or_default();
+
+    if property("LoadState") == "not-found" {
+        return Err(eyre!("async job '{id}' not found"));
+    }
+    match (property("ActiveState"), property("SubState")) {
+        ("active", "exited") | ("failed", _) | ("inactive", _) => {}
+        _ => return Ok(JobState::Running),
+    }
+
+    // ExecMainCode is a CLD_* code: 1 if the command exited, else it was
+    // killed by the signal in ExecMainStatus
+    let status: i32 = property("ExecMainStatus").parse().unwrap_or(-1);
+    let rc = match property("ExecMainCode") {
+        "1" => status,
+        _ => 128 + status,
+    };
+
+    // Only messages logged by the unit's processes, without systemd's. The
+    // journal interleaves stdout and stderr, so both end up in `stdout` and
+    // `stderr` stays empty
+    let journal = ctx.run_command_now(RunCommandOpts {
+        command: vec![
+            "journalctl",
+            "--output=cat",
+            "--no-pager",
+            "--quiet",
+            &format!("_SYSTEMD_UNIT={unit}"),
+        ],
+        capture: true,
+        read_only: true,
+        untimed,
+        ..Default::default()
+    })?;
+
+    Ok(JobState::Finished {
+        output: CommandOutput {
+            cmd: property("Description").to_owned(),
+            stdout: journal.stdout,
+            stderr: String::new(),
+            rc,
+        },
+        timed_out: (property("Result") == "timeout")
+            .then(|| parse_timespan(property("RuntimeMaxUSec")).unwrap_or_default()),
+    })
+}
+
+/// Remove the unit of the job `id`, stopping it if still running. With
+/// `untimed`, the task's `timeout` does not apply.
+fn cleanup(ctx: &TaskContextInner, id: &str, untimed: bool) -> eyre::Result<()> {
+    let unit = unit_name(id);
+    ctx.run_command_now(RunCommandOpts {
+        command: vec!["systemctl", "stop", unit.as_str()],
+        capture: true,
+        untimed,
+        ..Default::default()
+    })?;
+    // Only failed units are kept after stopping
+    let _ = ctx.run_command_now(RunCommandOpts {
+        command: vec!["systemctl", "reset-failed", unit.as_str()],
+        capture: true,
+        untimed,
+        ..Default::default()
+    });
+    Ok(())
+}
+
+strike! {
+    #[strikethrough[derive(Debug, Deserialize)]]
+    pub struct AsyncStatusTask {
+        pub jid: String,
+        pub mode: Option<pub enum {
+            #![serde(rename_all = "snake_case")]
+            Status,
+            Cleanup,
+        }>,
+    }
+}
+
+#[async_trait]
+impl StructuredTask for AsyncStatusTask {
+    async fn run_structured(&self, context: TaskContext) -> TaskResult {
+        let ctx = context.lock().await;
+        if !self
+            .jid
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(eyre!("invalid async job id '{}'", self.jid));
+        }
+
+        let mut result = Mapping::from_iter([
+            ("ansible_job_id".into(), Value::from(self.jid.as_str())),
+            ("started".into(), Value::from(1)),
+        ]);
+
+        if let Some(Mode::Cleanup) = self.mode {
+            cleanup(&ctx, &self.jid, false)?;
+            result.insert("erased".into(), Value::from(unit_name(&self.jid)));
+            return Ok(TaskOutput::changed(Some(Value::Mapping(result))));
+        }
+
+        match status(&ctx, &self.jid, false)? {
+            JobState::Running => {
+                result.insert("finished".into(), Value::from(0));
+                Ok(TaskOutput::ok(Some(Value::Mapping(result))))
+            }
+            JobState::Finished { output, timed_out } => {
+                let output = finished(output, timed_out)?;
+                result.insert("finished".into(), Value::from(1));
+                result.extend(output.to_result());
+                Ok(TaskOutput::ok(Some(Value::Mapping(result))))
+            }
+        }
+    }
+}
+
+inventory::submit! {
+    KeroseneTaskInfo::new_aliases("ansible.builtin.async_status", &["async_status"], &AsyncStatusTask::run)
+}
@@ -1,4 +1,4 @@
-use std::{collections::VecDeque, path::PathBuf};
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use eyre::{Context, eyre};
@@ -76,31 +76,48 @@ pub(crate) async fn resolve_local_file<'a>(
     subdirectory: &'a str,
     name: &'a str,
 ) -> eyre::Result<PathBuf> {
-    let name_path = PathBuf::from(name);
-    let mut possible_paths = VecDeque::new();
-
-    if name_path.is_relative() {
-        possible_paths.push_front(ctx.play_basedir.join(subdirectory).join(&name_path));
-        possible_paths.push_front(ctx.play_basedir.join(&name_path));
+    find_file(&ctx.search_path(), subdirectory, name)
+        .ok_or_else(|| eyre!("could not find file specified as '{name}'"))
+}
 
-        // Prepend role directories if present
-        for directory in ctx.resource_dirs.iter().rev() {
-            possible_paths.push_front(directory.join(&name_path));
-            possible_paths.push_front(directory.join(subdirectory).join(&name_path));
-        }
+/// Find `name` in the search path, trying `<dir>/<subdirectory>/<name>`
+/// before `<dir>/<name>` for each role directory. The playbook directory,
+/// last in the search path, tries `<dir>/<name>` first. Absolute names are
+/// used as is.
+pub(crate) fn find_file(
+    search_path: &[PathBuf],
+    subdirectory: &str,
+    name: &str,
+) -> Option<PathBuf> {
+    let name_path = Path::new(name);
+    let possible_paths: Vec<PathBuf> = if name_path.is_relative() {
+        let last = search_path.len().saturating_sub(1);
+        search_path
+            .iter()
+            .enumerate()
+            .flat_map(|(index, directory)| {
+                let nested = directory.join(subdirectory).join(name_path);
+                let direct = directory.join(name_path);
+                if index == last {
+                    [direct, nested]
+                } else {
+                    [nested, direct]
+                }
+            })
+            .collect()
     } else {
-        possible_paths.push_front(name_path);
-    }
+        vec![name_path.to_path_buf()]
+    };
 
     for path in possible_paths {
         trace!(name, subdirectory, possible_path = ?path, "resolving file");
         if std::fs::metadata(&path).is_ok() {
             trace!(name, subdirectory, possible_path = ?path, "file resolved");
-            return Ok(path);
+            return Some(path);
         }
     }
 
-    Err(eyre!("could not find file specified as '{name}'"))
+    None
 }
 
 pub(crate) fn build_install_command<'a>(
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use eyre::{Context, eyre};
+use kerosene::load_yaml;
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::Value;
+use tracing::debug;
+
+use crate::task::KeroseneTaskInfo;
+
+use super::{StructuredTask, TaskContext, TaskOutput, TaskResult, copy::resolve_local_file};
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum IncludeVarsTask {
+    /// Free-form `include_vars: path/to/file.yml`
+    File(String),
+    Options(IncludeVarsOptions),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncludeVarsOptions {
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub files_matching: Option<String>,
+    #[serde(default)]
+    pub ignore_files: Vec<String>,
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub ignore_unknown_extensions: bool,
+    /// Maximum directory depth to descend into; `0` means unlimited.
+    #[serde(default)]
+    pub depth: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub hash_behaviour: HashBehaviour,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashBehaviour {
+    #[default]
+    Replace,
+    Merge,
+}
+
+fn default_extensions() -> Vec<String> {
+    vec!["yaml".into(), "yml".into(), "json".into()]
+}
+
+#[async_trait]
+impl StructuredTask for IncludeVarsTask {
+    async fn run_structured(&self, context: TaskContext) -> TaskResult {
+        let free_form;
+        let options = match self {
+            Self::File(file) => {
+                free_form = IncludeVarsOptions::from_file(file.clone());
+                &free_form
+            }
+            Self::Options(options) => options,
+        };
+
+        let mut ctx = context.lock().await;
+
+        let files = match (&options.file, &options.dir) {
+            (Some(file), None) => vec![resolve_local_file(&ctx, "vars", file).await?],
+            (None, Some(dir)) => {
+                let dir = resolve_local_file(&ctx, "vars", dir).await?;
+                options.collect_dir(&dir)?
+            }
+            (Some(_), Some(_)) => {
+                return Err(eyre!(
+                    "include_vars accepts either 'file' or 'dir', not both"
+                ));
+            }
+            (None, None) => return Err(eyre!("include_vars requires either 'file' or 'dir'")),
+        };
+
+        let mut loaded = serde_yaml::Mapping::new();
+        for file in &files {
+            debug!(?file, "including vars");
+            let vars: Option<serde_yaml::Mapping> =
+                load_yaml(file).wrap_err_with(|| format!("failed to load vars from {file:?}"))?;
+            if let Some(vars) = vars {
+                for (key, value) in vars {
+                    insert_value(&mut loaded, key, value, &options.hash_behaviour);
+                }
+            }
+        }
+
+        let loaded: HashMap<String, Value> = match &options.name {
+            Some(name) => HashMap::from([(name.clone(), Value::Mapping(loaded))]),
+            None => serde_yaml::from_value(Value::Mapping(loaded))?,
+        };
+
+        for (key, value) in loaded {
+            match (&options.hash_behaviour, ctx.facts.remove(&key)) {
+                (HashBehaviour::Merge, Some(mut existing)) => {
+                    merge_values(&mut existing, value);
+                    ctx.facts.insert(key, existing);
+                }
+                _ => {
+                    ctx.facts.insert(key, value);
+                }
+            }
+        }
+
+        let mut result = serde_yaml::Mapping::new();
+        result.insert(
+            Value::String("ansible_included_var_files".into()),
+            Value::Sequence(
+                files
+                    .iter()
+                    .map(|file| Value::String(file.to_string_lossy().into_owned()))
+                    .collect(),
+            ),
+        );
+
+        Ok(TaskOutput::ok(Some(Value::Mapping(result))))
+    }
+}
+
+impl IncludeVarsOptions {
+    fn from_file(file: String) -> Self {
+        Self {
+            file: Some(file),
+            dir: None,
+            files_matching: None,
+            ignore_files: Vec::new(),
+            extensions: default_extensions(),
+            ignore_unknown_extensions: false,
+            depth: 0,
+            name: None,
+            hash_behaviour: HashBehaviour::Replace,
+        }
+    }
+
+    /// Collect all matching variable files below `dir`, sorted by path.
+    fn collect_dir(&self, dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+        let files_matching = self
+            .files_matching
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .wrap_err("invalid files_matching pattern")?;
+
+        let mut files = Vec::new();
+        let mut pending = vec![(dir.to_path_buf(), 1)];
+        while let Some((current, depth)) = pending.pop() {
+            let entries = std::fs::read_dir(&current)
+                .wrap_err_with(|| format!("failed to read {current:?}"))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    if self.depth == 0 || depth < self.depth {
+                        pending.push((path, depth + 1));
+                    }
+                    continue;
+                }
+
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                if self.ignore_files.contains(&file_name) {
+                    continue;
+                }
+                if let Some(pattern) = &files_matching
+                    && !pattern.is_match(&file_name)
+                {
+                    continue;
+                }
+
+                let extension = path
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                if !self.extensions.contains(&extension) {
+                    if self.ignore_unknown_extensions {
+                        continue;
+                    }
+                    return Err(eyre!(
+                        "{path:?} does not have a valid extension: {}",
+                        self.extensions.join(", ")
+                    ));
+                }
+
+                files.push(path);
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn insert_value(
+    target: &mut serde_yaml::Mapping,
+    key: Value,
+    value: Value,
+    hash_behaviour: &HashBehaviour,
+) {
+    match (hash_behaviour, target.get_mut(&key)) {
+        (HashBehaviour::Merge, Some(existing)) => merge_values(existing, value),
+        _ => {
+            target.insert(key, value);
+        }
+    }
+}
+
+/// Recursively merge `value` into `target`. Mappings are merged key by key,
+/// any other value replaces the existing one.
+pub(crate) fn merge_values(target: &mut Value, value: Value) {
+    match (target, value) {
+        (Value::Mapping(target), Value::Mapping(value)) => {
+            for (key, value) in value {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+inventory::submit! {
+    KeroseneTaskInfo::new_aliases("ansible.builtin.include_vars", &["include_vars"], &IncludeVarsTask::run)
+}
@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use eyre::eyre;
 use serde::Deserialize;
 use structstruck::strike;
-use tracing::{debug, warn};
+use tracing::debug;
 
 use crate::task::KeroseneTaskInfo;
 
@@ -19,6 +19,7 @@ strike! {
         EndPlay,
         FlushHandlers,
         Noop,
+        EndRole,
         RefreshInventory,
         ResetConnection,
         #[serde(untagged)]
@@ -26,15 +27,66 @@ strike! {
     });
 }
 
+/// What a `meta` action ends for the current host. Checked after every task,
+/// so the rest of the role, or of the play, is skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    /// `end_role`: continue after the current role
+    Role,
+    /// `end_host`: the host is done with this play
+    Host,
+    /// `end_play`/`end_batch`: no host continues with this play
+    Play,
+}
+
 #[async_trait]
 impl StructuredTask for MetaTask {
     async fn run_structured(&self, context: TaskContext) -> TaskResult {
         match &self.0 {
+            MetaTaskAction::ClearFacts => {
+                let mut ctx = context.lock().await;
+                debug!(host = ctx.inventory_hostname, "clearing facts");
+                // Only gathered and cacheable facts, which are all cached;
+                // registered results and other variables stay
+                let cached = ctx.fact_cache.vars(&ctx.inventory_hostname)?;
+                for name in cached.keys() {
+                    ctx.facts.remove(name);
+                }
+                ctx.facts.remove("ansible_facts");
+                ctx.fact_cache.clear(&ctx.inventory_hostname)?;
+            }
+            MetaTaskAction::ClearHostErrors => {
+                // A failing task aborts the whole run, so no host is ever
+                // left in a failed state to clear
+                debug!("no host errors to clear");
+            }
+            MetaTaskAction::EndBatch | MetaTaskAction::EndPlay => {
+                // Without `serial`, the batch is every host of the play
+                debug!("ending play");
+                context.lock().await.end = Some(End::Play);
+            }
+            MetaTaskAction::EndHost => {
+                let mut ctx = context.lock().await;
+                debug!(host = ctx.inventory_hostname, "ending play for host");
+                ctx.end = Some(End::Host);
+            }
+            MetaTaskAction::EndRole => {
+                let mut ctx = context.lock().await;
+                if ctx.resource_dirs.is_empty() {
+                    return Err(eyre!("meta: end_role can only be used in a role"));
+                }
+                debug!("ending role");
+                ctx.end = Some(End::Role);
+            }
             MetaTaskAction::FlushHandlers => {
                 debug!("flushing pending handlers");
                 crate::run_handlers(context).await?;
             }
             MetaTaskAction::Noop => {}
+            MetaTaskAction::RefreshInventory => {
+                debug!("refreshing inventory before the next play");
+                context.lock().await.refresh_inventory = true;
+            }
             MetaTaskAction::ResetConnection => {
                 debug!("triggering reset on command target");
                 context.lock().await.command_target.reset().await?;
@@ -42,9 +94,6 @@ impl StructuredTask for MetaTask {
             MetaTaskAction::Unknown(action) => {
                 return Err(eyre!("unknown meta action: {:?}", action));
             }
-            action => {
-                warn!(?action, "unhandled meta action");
-            }
         }
 
         Ok(TaskOutput::ok(None))
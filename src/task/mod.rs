@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     ffi::OsString,
     fmt::Debug,
     future::Future,
@@ -8,27 +8,34 @@ use std::{
     os::unix::process::ExitStatusExt,
     path::PathBuf,
     pin::Pin,
-    process::Stdio,
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, ExitStatus, Stdio},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
 use eyre::{Context, eyre};
 use serde::de::DeserializeOwned;
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 use tokio::sync::Mutex;
 use tracing::trace;
 
 use crate::{
-    command::{CommandTarget, PreparedCommand},
-    serde::task::HandlerDescription,
+    command::{BecomeMethod, CommandTarget, Elevate, PreparedCommand, TIMEOUT_KILL_AFTER},
+    fact_cache::FactCache,
+    render,
+    serde::{keywords::Keywords, task::HandlerDescription},
 };
 
+pub mod async_status;
 pub mod copy;
 pub mod curl;
 pub mod import_tasks;
+pub mod include_vars;
 pub mod meta;
 pub mod set_fact;
+pub mod setup;
 pub mod shell;
 pub mod systemd;
 pub mod template;
@@ -64,6 +71,10 @@ pub struct RunCommandOpts<'a> {
     pub working_directory: Option<&'a str>,
     pub stdin: Option<StdinSource>,
     pub capture: bool,
+    /// The command does not change the host, so it also runs in check mode
+    pub read_only: bool,
+    /// The command is not bounded by the task's `timeout`
+    pub untimed: bool,
 }
 
 impl Debug for RunCommandOpts<'_> {
@@ -80,44 +91,196 @@ impl Debug for RunCommandOpts<'_> {
                 },
             )
             .field("capture", &self.capture)
+            .field("read_only", &self.read_only)
+            .field("untimed", &self.untimed)
             .finish_non_exhaustive()
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CommandOutput {
+    /// The command as run, before privilege escalation. `sh -c` invocations
+    /// are shown as their script, like Ansible's `shell` module.
+    pub cmd: String,
     pub stdout: String,
     pub stderr: String,
     pub rc: i32,
 }
 
+impl CommandOutput {
+    fn new(command: &[&str], stdout: String, stderr: String, rc: i32) -> Self {
+        let cmd = match command {
+            [_, "-c", script] => (*script).to_owned(),
+            _ => shlex::try_join(command.iter().copied()).unwrap_or_else(|_| command.join(" ")),
+        };
+        Self {
+            cmd,
+            stdout,
+            stderr,
+            rc,
+        }
+    }
+
+    /// The registered result of a command: `cmd`, `rc`, `stdout`, `stderr`
+    /// and their `_lines` variants, without trailing newlines.
+    pub fn to_result(&self) -> Mapping {
+        let stdout = self.stdout.trim_end_matches('\n');
+        let stderr = self.stderr.trim_end_matches('\n');
+        let lines = |text: &str| Value::Sequence(text.lines().map(Value::from).collect());
+
+        Mapping::from_iter([
+            ("cmd".into(), Value::from(self.cmd.as_str())),
+            ("rc".into(), Value::from(self.rc)),
+            ("stdout".into(), Value::from(stdout)),
+            ("stdout_lines".into(), lines(stdout)),
+            ("stderr".into(), Value::from(stderr)),
+            ("stderr_lines".into(), lines(stderr)),
+        ])
+    }
+}
+
+/// A command that exited unsuccessfully. It is the root cause of the error
+/// returned by [`TaskContextInner::run_command_opts`], so that the output
+/// can still be registered.
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub output: CommandOutput,
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsuccessful run: exit status {}", self.output.rc)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// A command killed because its task's `timeout` passed. Registered like
+/// [`CommandFailed`], with `timedout: true`.
+#[derive(Debug)]
+pub struct CommandTimedOut {
+    pub output: CommandOutput,
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for CommandTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "timed out: not finished within {} seconds",
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for CommandTimedOut {}
+
+/// How long after `timeout(1)` should have killed a command its local
+/// process is killed.
+const WATCHDOG_GRACE: Duration = Duration::from_secs(5);
+
+/// Longest interval between checks on a command with a deadline.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Privilege escalation of the running task.
+#[derive(Clone, Debug)]
+pub struct Become {
+    pub user: String,
+    pub method: String,
+    /// From `become_flags`, replacing the method's defaults
+    pub flags: Option<String>,
+}
+
+/// A secret kept out of `Debug` output and traces.
+#[derive(Clone)]
+pub struct Password(String);
+
+impl Password {
+    pub fn new(password: String) -> Self {
+        Self(password)
+    }
+}
+
+impl Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(<redacted>)")
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct TaskContextInner {
+    /// Name of the current host in the inventory
+    pub inventory_hostname: String,
     pub play_basedir: PathBuf,
     pub resource_dirs: VecDeque<PathBuf>,
+    pub engine: render::Engine,
 
-    /// Four-layer variable system (lowest to highest precedence):
+    /// Layered variable system (lowest to highest precedence):
     /// 1. `role_defaults` — from `roles/<name>/defaults/main.yml`, scoped per role
-    /// 2. `facts` — from `set_fact`, persists across the entire play
-    /// 3. `role_play_vars` — from play's role definition `vars:`, scoped per role
-    /// 4. `task_vars` — from `vars:` on individual tasks/handlers, scoped per task
+    /// 2. `inventory_vars` — group and host vars of the current host
+    /// 3. `play_vars` — play `vars:`, `vars_prompt:` and `vars_files:`
+    /// 4. `role_vars` — from `roles/<name>/vars/main.yml`, scoped per role
+    /// 5. `facts` — gathered, from `set_fact` or the fact cache, persists
+    ///    across the entire play
+    /// 6. `role_play_vars` — from play's role definition `vars:`, scoped per role
+    /// 7. `task_vars` — from `vars:` on individual tasks/handlers, scoped per task
+    /// 8. `magic_vars` — `inventory_hostname`, `groups`, `hostvars` etc.
+    /// 9. `extra_vars` — from `-e/--extra-vars`, always win
     pub role_defaults: HashMap<String, Value>,
+    pub inventory_vars: HashMap<String, Value>,
+    pub play_vars: HashMap<String, Value>,
+    pub role_vars: HashMap<String, Value>,
     pub facts: HashMap<String, Value>,
     pub role_play_vars: HashMap<String, Value>,
     pub task_vars: HashMap<String, Value>,
+    pub magic_vars: HashMap<String, Value>,
+    pub extra_vars: HashMap<String, Value>,
+    pub fact_cache: FactCache,
 
     pub command_target: CommandTarget,
-    pub do_become_user: Option<String>,
-    pub pending_handlers: VecDeque<String>,
+    /// `ansible_user` of the host, which wins over `remote_user`
+    pub inventory_user: Option<String>,
+    pub do_become: Option<Become>,
+    /// Asked with `--ask-become-pass`, unless `ansible_become_password` is
+    /// set
+    pub become_password: Option<Password>,
+    /// Rendered `environment` of the running task, set for every command
+    pub environment: Vec<(String, String)>,
+    /// Used for tasks without a `timeout`, from `--task-timeout`
+    pub default_timeout: Option<Duration>,
+    /// `timeout` of the running task and when it passes, measured from
+    /// `apply_keywords`
+    pub timeout: Option<(Duration, Instant)>,
+    /// Set while running a task with `async`
+    pub async_job: Option<async_status::AsyncJob>,
+    /// Keywords of the current play, or of the role within it, which tasks
+    /// inherit
+    pub keywords: Keywords,
+    /// Indices of notified `known_handlers`, which run in declaration order
+    pub pending_handlers: BTreeSet<usize>,
+    /// Set by `meta` to stop running tasks for this host
+    pub end: Option<meta::End>,
+    /// Set by `meta: refresh_inventory` to re-read the inventory once this
+    /// host finished the play
+    pub refresh_inventory: bool,
 
-    pub known_handlers: HashMap<String, HandlerDescription>,
+    /// Handlers of the play's roles, then of the play, in declaration order
+    pub known_handlers: Vec<HandlerDescription>,
 }
 
 impl TaskContextInner {
     /// Returns the effective variable set with Ansible-correct precedence:
-    /// `task_vars` > `role_play_vars` > `facts` > `role_defaults`
+    /// `extra_vars` > `magic_vars` > `task_vars` > `role_play_vars` > `facts` >
+    /// `role_vars` > `play_vars` > `inventory_vars` > `role_defaults`
     pub fn merged_vars(&self) -> HashMap<String, Value> {
         let mut merged = self.role_defaults.clone();
+        merged.extend(
+            self.inventory_vars
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        merged.extend(self.play_vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        merged.extend(self.role_vars.iter().map(|(k, v)| (k.clone(), v.clone())));
         merged.extend(self.facts.iter().map(|(k, v)| (k.clone(), v.clone())));
         merged.extend(
             self.role_play_vars
@@ -125,9 +288,160 @@ impl TaskContextInner {
                 .map(|(k, v)| (k.clone(), v.clone())),
         );
         merged.extend(self.task_vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        merged.extend(self.magic_vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        // Role magic variables follow the innermost role resource directory,
+        // which is also pushed while running a role's handlers.
+        if let Some(role_path) = self.resource_dirs.front() {
+            if let Some(role_name) = role_path.file_name() {
+                merged.insert(
+                    "role_name".into(),
+                    Value::from(role_name.to_string_lossy().into_owned()),
+                );
+            }
+            merged.insert(
+                "role_path".into(),
+                Value::from(role_path.to_string_lossy().into_owned()),
+            );
+        }
+
+        merged.insert(
+            "ansible_search_path".into(),
+            Value::Sequence(
+                self.search_path()
+                    .iter()
+                    .map(|dir| Value::from(dir.to_string_lossy().into_owned()))
+                    .collect(),
+            ),
+        );
+
+        merged.insert(
+            "ansible_check_mode".into(),
+            Value::Bool(self.command_target.is_dry()),
+        );
+
+        merged.extend(self.extra_vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+
         merged
     }
 
+    /// Prepare running a task with `keywords`, as inherited from the current
+    /// play or role. `environment` is rendered with the current variables,
+    /// so task variables must already be set.
+    pub fn apply_keywords(&mut self, keywords: &Keywords) -> eyre::Result<()> {
+        let vars = self.vars();
+        let render =
+            |value: &Option<String>| value.as_deref().map(|v| vars.render_str(v)).transpose();
+
+        let do_become = match &keywords.r#become {
+            Some(value) => value.resolve(&vars)?,
+            None => false,
+        };
+        self.do_become = match do_become {
+            true => Some(Become {
+                user: render(&keywords.become_user)?.unwrap_or_else(|| "root".to_owned()),
+                method: render(&keywords.become_method)?.unwrap_or_else(|| "sudo".to_owned()),
+                flags: render(&keywords.become_flags)?,
+            }),
+            false => None,
+        };
+        let check_mode = match &keywords.check_mode {
+            Some(value) => value.resolve(&vars)?,
+            None => false,
+        };
+        self.command_target.set_dry(check_mode);
+        if let CommandTarget::Remote { user, .. } = &mut self.command_target {
+            *user = match &self.inventory_user {
+                Some(inventory_user) => Some(inventory_user.clone()),
+                None => render(&keywords.remote_user)?,
+            };
+        }
+        let timeout = keywords
+            .timeout
+            .as_ref()
+            .map(|value| value.resolve(&vars))
+            .transpose()?;
+        self.timeout = match timeout {
+            Some(0) => None,
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None => self.default_timeout,
+        }
+        .map(|timeout| (timeout, Instant::now() + timeout));
+
+        self.environment.clear();
+        if let Some(environment) = &keywords.environment {
+            let mut rendered = Vec::with_capacity(environment.len());
+            for (name, value) in environment {
+                if !is_env_name(name) {
+                    return Err(eyre!("invalid environment variable name '{name}'"));
+                }
+                let value = match vars.render_value(value)? {
+                    Value::String(value) => value,
+                    Value::Bool(value) => value.to_string(),
+                    Value::Number(value) => value.to_string(),
+                    Value::Null => String::new(),
+                    _ => return Err(eyre!("environment variable '{name}' is not a scalar")),
+                };
+                rendered.push((name.clone(), value));
+            }
+            rendered.sort();
+            self.environment = rendered;
+        }
+        Ok(())
+    }
+
+    /// Notify the handler named `name` (the last declared, if several share
+    /// it) and every handler listening to `name`.
+    pub fn notify(&mut self, name: &str) -> eyre::Result<()> {
+        let named = self
+            .known_handlers
+            .iter()
+            .rposition(|handler| handler.names.iter().any(|n| n == name));
+        let listening = self
+            .known_handlers
+            .iter()
+            .enumerate()
+            .filter(|(_, handler)| handler.listen.iter().any(|topic| topic == name))
+            .map(|(index, _)| index);
+
+        let notified: Vec<usize> = named.into_iter().chain(listening).collect();
+        if notified.is_empty() {
+            return Err(eyre!("Handler '{}' is not declared", name));
+        }
+        trace!(name, ?notified, "notifying handlers");
+        self.pending_handlers.extend(notified);
+        Ok(())
+    }
+
+    /// The effective variables, rendered on demand with the run's engine.
+    pub fn vars(&self) -> render::Vars {
+        render::Vars::new(&self.engine, self.merged_vars())
+    }
+
+    /// Directories searched for relative file names, innermost role first
+    /// and the playbook directory last.
+    pub fn search_path(&self) -> Vec<PathBuf> {
+        self.resource_dirs
+            .iter()
+            .chain(std::iter::once(&self.play_basedir))
+            .cloned()
+            .collect()
+    }
+
+    /// The password for escalating privileges: `ansible_become_password`
+    /// (or `ansible_become_pass`), else the one asked on the command line.
+    fn resolve_become_password(&self) -> eyre::Result<Option<Password>> {
+        let vars = self.vars();
+        for name in ["ansible_become_password", "ansible_become_pass"] {
+            match vars.get(name)? {
+                Some(Value::String(password)) => return Ok(Some(Password(password))),
+                Some(_) => return Err(eyre!("{name} is not a string")),
+                None => {}
+            }
+        }
+        Ok(self.become_password.clone())
+    }
+
     pub fn run_command(
         &self,
         working_directory: Option<&str>,
@@ -140,34 +454,68 @@ impl TaskContextInner {
         })
     }
 
+    /// Run a command, as a transient unit if the task is `async`.
     pub fn run_command_opts(&self, opts: RunCommandOpts) -> eyre::Result<CommandOutput> {
+        match &self.async_job {
+            Some(job) if !self.command_target.is_dry() => {
+                let launched = async_status::launch(self, job, opts)?;
+                if job.poll.is_zero() {
+                    Ok(launched)
+                } else {
+                    async_status::wait(self, job)
+                }
+            }
+            _ => self.run_command_now(opts),
+        }
+    }
+
+    /// Run a command and wait for it to exit.
+    pub(crate) fn run_command_now(&self, opts: RunCommandOpts) -> eyre::Result<CommandOutput> {
         let RunCommandOpts {
             command,
             working_directory,
             stdin,
             capture,
+            read_only,
+            untimed,
         } = opts;
 
-        trace!(?command, become = self.do_become_user, capture, "running command");
+        trace!(?command, become = ?self.do_become, capture, read_only, "running command");
 
-        // TODO: become_method
         let mut command_target = self.command_target.clone();
-        if let Some(become_user) = &self.do_become_user {
+        let mut password = None;
+        if let Some(Become {
+            user,
+            method,
+            flags,
+        }) = &self.do_become
+        {
+            let method: BecomeMethod = method.parse()?;
+            password = self.resolve_become_password()?;
+            if password.is_some() && !method.reads_password() {
+                return Err(eyre!(
+                    "become_method '{}' cannot be given a password: it only reads one from a terminal, so it must not ask for one",
+                    self.do_become.as_ref().unwrap().method
+                ));
+            }
+            let elevate = Elevate {
+                method,
+                user: user.clone(),
+                flags: flags
+                    .as_deref()
+                    .map(|flags| {
+                        shlex::split(flags).ok_or_else(|| eyre!("invalid become_flags '{flags}'"))
+                    })
+                    .transpose()?,
+                key: password.as_ref().map(|_| random_key()).transpose()?,
+            };
             match &mut command_target {
-                CommandTarget::Local { elevate, .. } => {
-                    *elevate = Some(vec![
-                        "sudo".to_string(),
-                        format!("--user={}", become_user),
-                        "--".to_string(),
-                    ]);
-                }
-                CommandTarget::Remote { elevate, .. } => {
-                    *elevate = Some(vec![
-                        "sudo".to_string(),
-                        format!("--user={}", become_user),
-                        "--".to_string(),
-                    ]);
+                CommandTarget::Local {
+                    elevate: target, ..
                 }
+                | CommandTarget::Remote {
+                    elevate: target, ..
+                } => *target = Some(elevate),
             }
         }
 
@@ -178,21 +526,54 @@ impl TaskContextInner {
             Vec::new()
         };
 
-        let mut child = PreparedCommand::new(&command_target, first)
+        // Escalation reading a password needs stdin and the output before
+        // the command starts
+        let elevated = match (&password, &command_target) {
+            (
+                Some(password),
+                CommandTarget::Local {
+                    elevate: Some(elevate),
+                    dry,
+                }
+                | CommandTarget::Remote {
+                    elevate: Some(elevate),
+                    dry,
+                    ..
+                },
+            ) if read_only || !*dry => Some((elevate, password)),
+            _ => None,
+        };
+        let piped = capture || elevated.is_some();
+
+        let task_timeout = self.timeout.filter(|_| !untimed);
+        let remaining =
+            task_timeout.map(|(_, deadline)| deadline.saturating_duration_since(Instant::now()));
+        if let (Some((timeout, _)), Some(Duration::ZERO)) = (task_timeout, remaining) {
+            let output = CommandOutput::new(&command, String::new(), String::new(), 124);
+            return Err(eyre::Report::new(CommandTimedOut { output, timeout }));
+        }
+
+        let mut prepared = PreparedCommand::new(&command_target, first);
+        if read_only {
+            prepared.read_only();
+        }
+        let mut child = prepared
             .chdir(working_directory.map(OsString::from))
+            .envs(&self.environment)
+            .timeout(remaining)
             .args(args)
             .to_command()?
-            .stdin(if stdin.is_some() {
+            .stdin(if stdin.is_some() || elevated.is_some() {
                 Stdio::piped()
             } else {
                 Stdio::null()
             })
-            .stdout(if capture {
+            .stdout(if piped {
                 Stdio::piped()
             } else {
                 Stdio::inherit()
             })
-            .stderr(if capture {
+            .stderr(if piped {
                 Stdio::piped()
             } else {
                 Stdio::inherit()
@@ -200,52 +581,294 @@ impl TaskContextInner {
             .spawn()
             .wrap_err("failed to spawn child")?;
 
-        if let Some(source) = stdin {
-            let mut child_stdin = child.stdin.take().unwrap();
-            match source {
-                StdinSource::Bytes(bytes) => {
-                    child_stdin
-                        .write_all(&bytes)
-                        .wrap_err("failed to write stdin")?;
-                }
-                StdinSource::Reader(mut reader) => {
-                    std::io::copy(&mut reader, &mut child_stdin)
-                        .wrap_err("failed to write stdin")?;
+        // The child's pipes are served on a thread, so that the child can be
+        // killed while they are still open
+        let pipes = ChildPipes {
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+        };
+        let (io_done, io) = std::sync::mpsc::channel();
+        let elevated = elevated.map(|(elevate, password)| (elevate.clone(), password.clone()));
+        std::thread::spawn(move || {
+            let result = match elevated {
+                Some((elevate, password)) => {
+                    communicate_elevated(pipes, &elevate, &password, stdin, capture)
                 }
-            }
-            // Drop stdin so the child sees EOF
-            drop(child_stdin);
-        }
+                None => communicate(pipes, stdin),
+            };
+            let _ = io_done.send(result);
+        });
+
+        // Kill the local ssh or sh if even `timeout(1)` did not end the
+        // command, such as when the connection hangs
+        let deadline = remaining
+            .map(|remaining| Instant::now() + remaining + TIMEOUT_KILL_AFTER + WATCHDOG_GRACE);
+        let (output_status, killed) =
+            wait_until(&mut child, deadline).wrap_err("failed to wait for child")?;
+        // Processes left behind by a killed command may keep its pipes open
+        let (stdout, stderr) = match killed {
+            true => io
+                .recv_timeout(WATCHDOG_GRACE)
+                .unwrap_or(Ok(Default::default())),
+            false => io
+                .recv()
+                .map_err(|_| eyre!("command I/O thread panicked"))?,
+        }?;
 
-        let output = child
-            .wait_with_output()
-            .wrap_err("failed to wait for child")?;
-        let rc = output
-            .status
+        let rc = output_status
             .code()
-            .unwrap_or_else(|| 128 + output.status.signal().unwrap_or(0));
+            .unwrap_or_else(|| 128 + output_status.signal().unwrap_or(0));
 
-        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
-        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        let stdout = String::from_utf8_lossy(&stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&stderr).into_owned();
+        let output = CommandOutput::new(&command, stdout, stderr, rc);
 
-        if !output.status.success() {
-            let mut err = eyre!("unsuccessful run: exit status {rc}");
+        // `timeout(1)` exits with 124, or 137 once it had to kill
+        let timed_out = killed
+            || ((rc == 124 || rc == 137)
+                && task_timeout.is_some_and(|(_, deadline)| Instant::now() >= deadline));
+        if let (true, Some((timeout, _))) = (timed_out, task_timeout) {
+            return Err(eyre::Report::new(CommandTimedOut { output, timeout }));
+        }
+
+        if !output_status.success() {
+            let stderr =
+                (capture && !output.stderr.is_empty()).then(|| output.stderr.trim_end().to_owned());
+            let mut err = eyre::Report::new(CommandFailed { output });
 
             if rc == 255 && matches!(command_target, CommandTarget::Remote { .. }) {
                 err = err.wrap_err("SSH connection failed (exit code 255)");
             }
 
-            if capture && !stderr.is_empty() {
-                err = err.wrap_err(stderr.trim_end().to_owned());
+            if let Some(stderr) = stderr {
+                err = err.wrap_err(stderr);
             }
 
             return Err(err);
         }
 
-        Ok(CommandOutput { stdout, stderr, rc })
+        Ok(output)
     }
 }
 
+/// A random key, such as identifying the password prompt and success marker
+/// of one escalated command.
+pub(crate) fn random_key() -> eyre::Result<String> {
+    let mut key = [0u8; 16];
+    getrandom::fill(&mut key).map_err(|err| eyre!("failed to generate random key: {err}"))?;
+    Ok(key.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Whether `name` is a portable environment variable name,
+/// `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn write_stdin(mut child_stdin: ChildStdin, source: StdinSource) -> std::io::Result<()> {
+    match source {
+        StdinSource::Bytes(bytes) => child_stdin.write_all(&bytes),
+        StdinSource::Reader(mut reader) => std::io::copy(&mut reader, &mut child_stdin).map(|_| ()),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Pipes taken from a spawned child.
+struct ChildPipes {
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+}
+
+/// Wait for `child` to exit, killing it once `deadline` passes. Returns
+/// whether it was killed.
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> std::io::Result<(ExitStatus, bool)> {
+    let Some(deadline) = deadline else {
+        return Ok((child.wait()?, false));
+    };
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            // Not reaped yet, so the process cannot have been replaced
+            child.kill()?;
+            return Ok((child.wait()?, true));
+        }
+        std::thread::sleep(interval.min(left));
+        interval = (interval * 2).min(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// Write `stdin` to the child and read its piped output.
+fn communicate(pipes: ChildPipes, stdin: Option<StdinSource>) -> eyre::Result<(Vec<u8>, Vec<u8>)> {
+    // Dropping stdin after writing lets the child see EOF
+    let writer = pipes.stdin.map(|input| {
+        std::thread::spawn(move || match stdin {
+            Some(source) => write_stdin(input, source),
+            None => Ok(()),
+        })
+    });
+    let stderr_reader = pipes.stderr.map(|mut pipe| {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            pipe.read_to_end(&mut buffer).map(|_| buffer)
+        })
+    });
+
+    let mut stdout = Vec::new();
+    if let Some(mut pipe) = pipes.stdout {
+        pipe.read_to_end(&mut stdout)
+            .wrap_err("failed to read stdout")?;
+    }
+    let stderr = match stderr_reader {
+        Some(reader) => reader
+            .join()
+            .map_err(|_| eyre!("stderr reader panicked"))?
+            .wrap_err("failed to read stderr")?,
+        None => Vec::new(),
+    };
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| eyre!("stdin writer panicked"))?
+            .wrap_err("failed to write stdin")?;
+    }
+
+    Ok((stdout, stderr))
+}
+
+/// Answer the password prompt of `elevate`, then feed `stdin` once the
+/// command runs with escalated privileges. Returns the captured stdout and
+/// stderr, without the prompt and the success marker.
+fn communicate_elevated(
+    pipes: ChildPipes,
+    elevate: &Elevate,
+    password: &Password,
+    stdin: Option<StdinSource>,
+    capture: bool,
+) -> eyre::Result<(Vec<u8>, Vec<u8>)> {
+    let (sender, receiver) = std::sync::mpsc::channel::<(Stream, Option<Vec<u8>>)>();
+    let readers: [(Stream, Box<dyn Read + Send>); 2] = [
+        (Stream::Stdout, Box::new(pipes.stdout.unwrap())),
+        (Stream::Stderr, Box::new(pipes.stderr.unwrap())),
+    ];
+    for (stream, mut reader) in readers {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 8192];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => None,
+                    Ok(len) => Some(buffer[..len].to_vec()),
+                };
+                let done = chunk.is_none();
+                if sender.send((stream, chunk)).is_err() || done {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    let marker = format!("{}\n", elevate.success_marker().unwrap());
+    let mut child_stdin = pipes.stdin;
+    let mut stdin = stdin;
+    let mut writer = None;
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut password_sent = false;
+    let mut escalated = false;
+    let mut open = 2;
+
+    while open > 0 {
+        let Ok((stream, chunk)) = receiver.recv() else {
+            break;
+        };
+        let Some(chunk) = chunk else {
+            open -= 1;
+            continue;
+        };
+
+        if escalated && !capture {
+            match stream {
+                Stream::Stdout => std::io::stdout().write_all(&chunk)?,
+                Stream::Stderr => std::io::stderr().write_all(&chunk)?,
+            }
+            continue;
+        }
+        let buffer = match stream {
+            Stream::Stdout => &mut stdout,
+            Stream::Stderr => &mut stderr,
+        };
+        buffer.extend(chunk);
+        if escalated {
+            continue;
+        }
+
+        if let Some(at) = elevate.find_prompt(buffer) {
+            buffer.truncate(at);
+            if password_sent {
+                // Closing stdin makes the prompt fail
+                return Err(eyre!("incorrect become password"));
+            }
+            trace!("answering become password prompt");
+            let input = child_stdin.as_mut().unwrap();
+            input
+                .write_all(password.0.as_bytes())
+                .and_then(|()| input.write_all(b"\n"))
+                .wrap_err("failed to write become password")?;
+            password_sent = true;
+        }
+
+        if let Some(at) = stdout
+            .windows(marker.len())
+            .position(|window| window == marker.as_bytes())
+        {
+            stdout.drain(..at + marker.len());
+            escalated = true;
+
+            // Dropping stdin after writing lets the child see EOF
+            let input = child_stdin.take().unwrap();
+            let source = stdin.take();
+            writer = Some(std::thread::spawn(move || match source {
+                Some(source) => write_stdin(input, source),
+                None => Ok(()),
+            }));
+
+            if !capture {
+                std::io::stdout().write_all(&std::mem::take(&mut stdout))?;
+                std::io::stderr().write_all(&std::mem::take(&mut stderr))?;
+            }
+        }
+    }
+
+    if !escalated {
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(eyre!("privilege escalation failed: {}", stderr.trim_end()));
+    }
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| eyre!("stdin writer panicked"))?
+            .wrap_err("failed to write stdin")?;
+    }
+
+    Ok((stdout, stderr))
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct TaskContext {
     inner: Arc<Mutex<TaskContextInner>>,
@@ -260,10 +883,11 @@ impl Deref for TaskContext {
 }
 
 impl TaskContext {
-    pub fn new(play_basedir: PathBuf) -> Self {
+    pub fn new(play_basedir: PathBuf, engine: render::Engine) -> Self {
         Self {
             inner: Arc::new(Mutex::new(TaskContextInner {
                 play_basedir,
+                engine,
                 ..Default::default()
             })),
         }
@@ -295,6 +919,62 @@ pub type TaskResult = eyre::Result<TaskOutput>;
 pub type TaskFut = Pin<Box<dyn Future<Output = TaskResult> + Send + 'static>>;
 pub type TaskRun = dyn Fn(TaskContext, Value) -> TaskFut + Send + Sync + 'static;
 
+/// The value registered for a task: `failed`, `changed`, `msg` and timing,
+/// merged with the module's output, or with the output of the command that
+/// failed it.
+pub fn registered_result(
+    result: &TaskResult,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) -> Value {
+    let mut registered = Mapping::new();
+    let (changed, msg) = match result {
+        Ok(output) => {
+            if let Some(Value::Mapping(output)) = &output.output {
+                registered.extend(output.clone());
+            }
+            (output.changed, String::new())
+        }
+        Err(err) => {
+            for cause in err.chain() {
+                if let Some(failed) = cause.downcast_ref::<CommandFailed>() {
+                    registered.extend(failed.output.to_result());
+                    break;
+                }
+                if let Some(timed_out) = cause.downcast_ref::<CommandTimedOut>() {
+                    registered.extend(timed_out.output.to_result());
+                    registered.insert("timedout".into(), Value::Bool(true));
+                    break;
+                }
+            }
+            (false, format!("{err:#}"))
+        }
+    };
+
+    let delta = (end - start).to_std().unwrap_or_default();
+    let delta = format!(
+        "{}:{:02}:{:02}.{:06}",
+        delta.as_secs() / 3600,
+        delta.as_secs() / 60 % 60,
+        delta.as_secs() % 60,
+        delta.subsec_micros()
+    );
+
+    registered.extend([
+        ("failed".into(), Value::from(result.is_err())),
+        ("changed".into(), Value::from(changed)),
+        ("msg".into(), Value::from(msg)),
+        ("start".into(), Value::from(format_time(start))),
+        ("end".into(), Value::from(format_time(end))),
+        ("delta".into(), Value::from(delta)),
+    ]);
+    Value::Mapping(registered)
+}
+
+fn format_time(time: DateTime<Local>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.6f").to_string()
+}
+
 #[async_trait]
 pub trait Task
 where
@@ -2,11 +2,11 @@ use std::collections::HashMap;
 
 use async_trait::async_trait;
 use serde::Deserialize;
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 use structstruck::strike;
 use tracing::debug;
 
-use crate::task::KeroseneTaskInfo;
+use crate::{render, task::KeroseneTaskInfo};
 
 use super::{StructuredTask, TaskContext, TaskOutput, TaskResult};
 
@@ -25,9 +25,22 @@ impl StructuredTask for SetFactTask {
     async fn run_structured(&self, context: TaskContext) -> TaskResult {
         let mut context = context.lock().await;
 
+        // Values were rendered with the task's arguments and may hold host
+        // output, so they are stored as literals
+        let mut cached = Mapping::new();
         for (key, value) in &self.facts {
             debug!(key, cacheable = self.cacheable, ?value, "setting fact");
-            context.facts.insert(key.clone(), value.clone());
+            let value = render::mark_unsafe(value.clone());
+            if self.cacheable {
+                cached.insert(key.as_str().into(), value.clone());
+            }
+            context.facts.insert(key.clone(), value);
+        }
+
+        if !cached.is_empty() {
+            context
+                .fact_cache
+                .update(&context.inventory_hostname, cached)?;
         }
 
         Ok(TaskOutput::ok(None))
@@ -0,0 +1,814 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use async_trait::async_trait;
+use eyre::{Context, eyre};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use tracing::debug;
+
+use crate::{render, task::KeroseneTaskInfo};
+
+use super::{
+    RunCommandOpts, StructuredTask, TaskContext, TaskOutput, TaskResult, shell::default_executable,
+};
+
+/// `gather_subset` as a list or a comma separated string.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum GatherSubset {
+    Csv(String),
+    List(Vec<String>),
+}
+
+impl GatherSubset {
+    fn entries(&self) -> Vec<String> {
+        match self {
+            Self::Csv(csv) => csv
+                .split(',')
+                .map(|entry| entry.trim().to_owned())
+                .collect(),
+            Self::List(list) => list.clone(),
+        }
+    }
+}
+
+/// `filter` as a single wildcard or a list of them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Filter {
+    Pattern(String),
+    Patterns(Vec<String>),
+}
+
+impl Filter {
+    fn patterns(&self) -> &[String] {
+        match self {
+            Self::Pattern(pattern) => std::slice::from_ref(pattern),
+            Self::Patterns(patterns) => patterns,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SetupTask {
+    #[serde(default)]
+    pub gather_subset: Option<GatherSubset>,
+    /// Wildcards matched against fact names, with or without the `ansible_`
+    /// prefix; all facts are kept when unset.
+    #[serde(default)]
+    pub filter: Option<Filter>,
+}
+
+/// A group of facts, collected by one part of the gathering script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Platform,
+    Distribution,
+    User,
+    ServiceManager,
+    Packages,
+    Cpu,
+    Memory,
+    Mounts,
+    Interfaces,
+    Virtualization,
+}
+
+const MIN: &[Section] = &[
+    Section::Platform,
+    Section::Distribution,
+    Section::User,
+    Section::ServiceManager,
+    Section::Packages,
+];
+const HARDWARE: &[Section] = &[Section::Cpu, Section::Memory, Section::Mounts];
+const NETWORK: &[Section] = &[Section::Interfaces];
+const VIRTUAL: &[Section] = &[Section::Virtualization];
+
+fn subset(name: &str) -> eyre::Result<&'static [Section]> {
+    Ok(match name {
+        "min" => MIN,
+        "hardware" => HARDWARE,
+        "network" => NETWORK,
+        "virtual" => VIRTUAL,
+        other => {
+            return Err(eyre!(
+                "unknown gather_subset '{other}', expected all, min, hardware, network or virtual"
+            ));
+        }
+    })
+}
+
+/// Resolve `gather_subset` entries the way Ansible does: `all` unless only
+/// exclusions are given, `!all` gathers just what is listed, `!name`
+/// excludes a subset, and `min` is always gathered unless excluded
+/// explicitly.
+fn sections(entries: &[String]) -> eyre::Result<BTreeSet<Section>> {
+    let all: Vec<Section> = [MIN, HARDWARE, NETWORK, VIRTUAL].concat();
+    let mut included = BTreeSet::new();
+    let mut excluded = BTreeSet::new();
+    let mut exclude_all = false;
+    let mut exclude_min = false;
+
+    for entry in entries.iter().filter(|entry| !entry.is_empty()) {
+        match entry.strip_prefix('!') {
+            Some("all") => exclude_all = true,
+            Some("min") => exclude_min = true,
+            Some(name) => excluded.extend(subset(name)?),
+            None if entry == "all" => included.extend(&all),
+            None => included.extend(subset(entry)?),
+        }
+    }
+
+    if included.is_empty() && !exclude_all {
+        included.extend(&all);
+    }
+    if !exclude_min {
+        included.extend(MIN);
+    }
+    Ok(&included - &excluded)
+}
+
+/// The commands printing a section's raw data, each part under an `@@name`
+/// marker line. Only POSIX sh, coreutils, procfs/sysfs and systemd tools are
+/// used; anything missing simply yields no output.
+fn script(section: Section) -> &'static str {
+    match section {
+        Section::Platform => {
+            "echo @@platform; uname -s; uname -r; uname -v; uname -m; uname -n; \
+             cat /etc/machine-id 2>/dev/null"
+        }
+        Section::Distribution => {
+            "echo @@os_release; cat /etc/os-release 2>/dev/null || cat /usr/lib/os-release 2>/dev/null"
+        }
+        Section::User => r#"echo @@user; id -un; id -u; id -g; printf '%s\n%s\n' "$HOME" "$SHELL""#,
+        Section::ServiceManager => {
+            "echo @@service_mgr; cat /proc/1/comm 2>/dev/null; systemctl --version 2>/dev/null | head -n 2"
+        }
+        Section::Packages => {
+            "echo @@packages; test -e /run/ostree-booted && echo ostree-booted; \
+             for tool in rpm-ostree dnf yum apt-get zypper pacman apk; do \
+             command -v $tool >/dev/null 2>&1 && echo $tool; done"
+        }
+        Section::Cpu => "echo @@nproc; nproc 2>/dev/null; echo @@cpuinfo; cat /proc/cpuinfo",
+        Section::Memory => "echo @@meminfo; cat /proc/meminfo",
+        Section::Mounts => {
+            "echo @@mounts; cat /proc/mounts; echo @@df; timeout 10 df -P -k -l 2>/dev/null"
+        }
+        Section::Interfaces => {
+            r#"echo @@interfaces; for dev in /sys/class/net/*; do [ -e "$dev" ] || continue; \
+             kind=; [ -d "$dev/bridge" ] && kind=bridge; [ -d "$dev/bonding" ] && kind=bonding; \
+             printf '%s|%s|%s|%s|%s|%s\n' "${dev##*/}" "$(cat "$dev/address" 2>/dev/null)" \
+             "$(cat "$dev/mtu" 2>/dev/null)" "$(cat "$dev/operstate" 2>/dev/null)" \
+             "$(cat "$dev/type" 2>/dev/null)" "$kind"; done; \
+             echo @@addresses; ip -j addr show 2>/dev/null; \
+             echo @@routes4; ip -j -4 route show default 2>/dev/null; \
+             echo @@routes6; ip -j -6 route show default 2>/dev/null"#
+        }
+        Section::Virtualization => {
+            "echo @@virt; systemd-detect-virt --vm 2>/dev/null; systemd-detect-virt --container 2>/dev/null"
+        }
+    }
+}
+
+/// Split the script output into its `@@name` parts.
+fn split_parts(output: &str) -> HashMap<&str, Vec<&str>> {
+    let mut parts: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut current = None;
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix("@@") {
+            current = Some(name);
+            parts.entry(name).or_default();
+        } else if let Some(name) = current {
+            parts.entry(name).or_default().push(line);
+        }
+    }
+    parts
+}
+
+type Facts = BTreeMap<String, Value>;
+
+fn platform(lines: &[&str], facts: &mut Facts) {
+    let line = |index: usize| lines.get(index).copied().unwrap_or_default().trim();
+    let nodename = line(4);
+    let (hostname, domain) = nodename.split_once('.').unwrap_or((nodename, ""));
+
+    facts.insert("system".into(), line(0).into());
+    facts.insert("kernel".into(), line(1).into());
+    facts.insert("kernel_version".into(), line(2).into());
+    facts.insert("machine".into(), line(3).into());
+    facts.insert("architecture".into(), line(3).into());
+    facts.insert("nodename".into(), nodename.into());
+    facts.insert("hostname".into(), hostname.into());
+    facts.insert("fqdn".into(), nodename.into());
+    facts.insert("domain".into(), domain.into());
+    if !line(5).is_empty() {
+        facts.insert("machine_id".into(), line(5).into());
+    }
+}
+
+/// Ansible's distribution name for an os-release `ID`.
+fn distribution_name(id: &str, name: &str) -> String {
+    match id {
+        "fedora" => "Fedora",
+        "rhel" => "RedHat",
+        "centos" => "CentOS",
+        "rocky" => "Rocky",
+        "almalinux" => "AlmaLinux",
+        "ol" => "OracleLinux",
+        "amzn" => "Amazon",
+        "debian" => "Debian",
+        "ubuntu" => "Ubuntu",
+        "arch" => "Archlinux",
+        "alpine" => "Alpine",
+        "flatcar" => "Flatcar",
+        "opensuse-leap" | "opensuse-tumbleweed" | "opensuse" => "openSUSE",
+        "sles" => "SLES",
+        _ => name.split_whitespace().next().unwrap_or(id),
+    }
+    .to_owned()
+}
+
+fn os_family(ids: &[&str]) -> Option<&'static str> {
+    ids.iter().find_map(|id| {
+        Some(match *id {
+            "fedora" | "rhel" | "centos" | "rocky" | "almalinux" | "ol" | "amzn" => "RedHat",
+            "debian" | "ubuntu" => "Debian",
+            "arch" => "Archlinux",
+            "alpine" => "Alpine",
+            "flatcar" => "Flatcar",
+            id if id.starts_with("opensuse") || id == "sles" || id == "suse" => "Suse",
+            _ => return None,
+        })
+    })
+}
+
+fn distribution(lines: &[&str], facts: &mut Facts) {
+    let os_release: HashMap<&str, String> = lines
+        .iter()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .or_else(|| {
+                    value
+                        .strip_prefix('\'')
+                        .and_then(|value| value.strip_suffix('\''))
+                })
+                .unwrap_or(value);
+            (key.trim(), value.replace("\\\"", "\""))
+        })
+        .collect();
+    let field = |key: &str| os_release.get(key).cloned().unwrap_or_default();
+
+    let id = field("ID");
+    let version = field("VERSION_ID");
+    let id_like = field("ID_LIKE");
+    let ids: Vec<&str> = std::iter::once(id.as_str())
+        .chain(id_like.split_whitespace())
+        .collect();
+
+    facts.insert(
+        "distribution".into(),
+        distribution_name(&id, &field("NAME")).into(),
+    );
+    facts.insert(
+        "distribution_major_version".into(),
+        version.split('.').next().unwrap_or_default().into(),
+    );
+    facts.insert("distribution_version".into(), version.into());
+    facts.insert(
+        "distribution_release".into(),
+        field("VERSION_CODENAME").into(),
+    );
+    facts.insert("distribution_variant".into(), field("VARIANT_ID").into());
+    facts.insert(
+        "os_family".into(),
+        os_family(&ids)
+            .map(str::to_owned)
+            .unwrap_or_else(|| distribution_name(&id, &field("NAME")))
+            .into(),
+    );
+    facts.insert("is_flatcar".into(), (id == "flatcar").into());
+}
+
+fn user(lines: &[&str], facts: &mut Facts) {
+    let line = |index: usize| lines.get(index).copied().unwrap_or_default().trim();
+    let number = |index: usize| line(index).parse::<u64>().map_or(Value::Null, Value::from);
+
+    facts.insert("user_id".into(), line(0).into());
+    facts.insert("user_uid".into(), number(1));
+    facts.insert("user_gid".into(), number(2));
+    facts.insert("user_dir".into(), line(3).into());
+    facts.insert("user_shell".into(), line(4).into());
+}
+
+fn service_manager(lines: &[&str], facts: &mut Facts) {
+    let comm = lines.first().copied().unwrap_or_default().trim();
+    let service_mgr = if comm.is_empty() || comm == "init" {
+        "sysvinit"
+    } else {
+        comm
+    };
+    facts.insert("service_mgr".into(), service_mgr.into());
+
+    // "systemd 254 (254.5-2.fc39)" followed by the feature flags
+    if let Some(version) = lines
+        .get(1)
+        .and_then(|line| line.strip_prefix("systemd "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|version| version.parse::<u64>().ok())
+    {
+        facts.insert(
+            "systemd".into(),
+            Value::Mapping(Mapping::from_iter([
+                ("version".into(), Value::from(version)),
+                (
+                    "features".into(),
+                    Value::from(lines.get(2).copied().unwrap_or_default().trim()),
+                ),
+            ])),
+        );
+    }
+}
+
+fn packages(lines: &[&str], facts: &mut Facts) {
+    let has = |tool: &str| lines.iter().any(|line| line.trim() == tool);
+    let is_rpm_ostree = has("ostree-booted") && has("rpm-ostree");
+
+    let pkg_mgr = if is_rpm_ostree {
+        "atomic_container"
+    } else {
+        [
+            ("dnf", "dnf"),
+            ("yum", "yum"),
+            ("apt-get", "apt"),
+            ("zypper", "zypper"),
+            ("pacman", "pacman"),
+            ("apk", "apk"),
+        ]
+        .into_iter()
+        .find_map(|(tool, name)| has(tool).then_some(name))
+        .unwrap_or("unknown")
+    };
+
+    facts.insert("pkg_mgr".into(), pkg_mgr.into());
+    facts.insert("is_rpm_ostree".into(), is_rpm_ostree.into());
+}
+
+fn cpu(nproc: &[&str], cpuinfo: &[&str], facts: &mut Facts) {
+    let field = |line: &str| {
+        line.split_once(':')
+            .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+    };
+    let fields: Vec<(String, String)> = cpuinfo.iter().filter_map(|line| field(line)).collect();
+
+    let vcpus = fields.iter().filter(|(key, _)| key == "processor").count();
+    let sockets = fields
+        .iter()
+        .filter(|(key, _)| key == "physical id")
+        .map(|(_, value)| value)
+        .collect::<BTreeSet<_>>()
+        .len()
+        .max(1);
+    let cores = fields
+        .iter()
+        .find(|(key, _)| key == "cpu cores")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(vcpus.max(1) / sockets);
+    let nproc = nproc
+        .first()
+        .and_then(|line| line.trim().parse::<usize>().ok())
+        .unwrap_or(vcpus);
+
+    facts.insert("processor_count".into(), sockets.into());
+    facts.insert("processor_cores".into(), cores.into());
+    facts.insert("processor_vcpus".into(), vcpus.into());
+    facts.insert("processor_nproc".into(), nproc.into());
+    facts.insert(
+        "processor_threads_per_core".into(),
+        (vcpus / (sockets * cores).max(1)).max(1).into(),
+    );
+}
+
+fn memory(lines: &[&str], facts: &mut Facts) {
+    let meminfo: HashMap<&str, u64> = lines
+        .iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let kb = value.split_whitespace().next()?.parse().ok()?;
+            Some((key, kb))
+        })
+        .collect();
+    let mb = |key: &str| meminfo.get(key).copied().unwrap_or_default() / 1024;
+
+    let (total, free) = (mb("MemTotal"), mb("MemFree"));
+    let nocache_free = free + mb("Buffers") + mb("Cached");
+    let (swap_total, swap_free) = (mb("SwapTotal"), mb("SwapFree"));
+    let mapping = |entries: &[(&str, u64)]| {
+        Value::Mapping(
+            entries
+                .iter()
+                .map(|(key, value)| (Value::from(*key), Value::from(*value)))
+                .collect(),
+        )
+    };
+
+    facts.insert("memtotal_mb".into(), total.into());
+    facts.insert("memfree_mb".into(), free.into());
+    facts.insert("swaptotal_mb".into(), swap_total.into());
+    facts.insert("swapfree_mb".into(), swap_free.into());
+    facts.insert(
+        "memory_mb".into(),
+        Value::Mapping(Mapping::from_iter([
+            (
+                "real".into(),
+                mapping(&[
+                    ("total", total),
+                    ("free", free),
+                    ("used", total.saturating_sub(free)),
+                ]),
+            ),
+            (
+                "nocache".into(),
+                mapping(&[
+                    ("free", nocache_free),
+                    ("used", total.saturating_sub(nocache_free)),
+                ]),
+            ),
+            (
+                "swap".into(),
+                mapping(&[
+                    ("total", swap_total),
+                    ("free", swap_free),
+                    ("used", swap_total.saturating_sub(swap_free)),
+                    ("cached", mb("SwapCached")),
+                ]),
+            ),
+        ])),
+    );
+}
+
+/// Undo the octal escapes of `/proc/mounts` (`\040` for a space).
+fn unescape_mount(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let code = rest.get(index + 1..index + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(char::from(byte));
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Mounted filesystems that `df` reports on, i.e. local filesystems with a
+/// size, with their `/proc/mounts` details.
+fn mounts(proc_mounts: &[&str], df: &[&str], facts: &mut Facts) {
+    let details: HashMap<String, (String, String, String)> = proc_mounts
+        .iter()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [device, mount, fstype, options, ..] = fields[..] else {
+                return None;
+            };
+            Some((
+                unescape_mount(mount),
+                (
+                    unescape_mount(device),
+                    fstype.to_owned(),
+                    options.to_owned(),
+                ),
+            ))
+        })
+        .collect();
+
+    let mut mounts = Vec::new();
+    for line in df.iter().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, blocks, _, available, _, mount] = fields[..] else {
+            continue;
+        };
+        let Some((device, fstype, options)) = details.get(mount) else {
+            continue;
+        };
+        let kb = |value: &str| value.parse::<u64>().unwrap_or_default() * 1024;
+        mounts.push(Value::Mapping(Mapping::from_iter([
+            ("mount".into(), Value::from(mount)),
+            ("device".into(), Value::from(device.as_str())),
+            ("fstype".into(), Value::from(fstype.as_str())),
+            ("options".into(), Value::from(options.as_str())),
+            ("size_total".into(), Value::from(kb(blocks))),
+            ("size_available".into(), Value::from(kb(available))),
+        ])));
+    }
+    facts.insert("mounts".into(), Value::Sequence(mounts));
+}
+
+#[derive(Debug, Deserialize)]
+struct IpLink {
+    ifname: String,
+    #[serde(default)]
+    addr_info: Vec<IpAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IpAddress {
+    family: String,
+    local: String,
+    prefixlen: u8,
+    #[serde(default)]
+    broadcast: Option<String>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IpRoute {
+    #[serde(default)]
+    gateway: Option<String>,
+    dev: String,
+}
+
+fn ipv4_details(address: &IpAddress) -> Value {
+    let mut details = Mapping::from_iter([("address".into(), Value::from(address.local.as_str()))]);
+    if let Ok(net) = format!("{}/{}", address.local, address.prefixlen).parse::<IpNet>() {
+        details.insert("netmask".into(), net.netmask().to_string().into());
+        details.insert("network".into(), net.network().to_string().into());
+    }
+    if let Some(broadcast) = &address.broadcast {
+        details.insert("broadcast".into(), broadcast.as_str().into());
+    }
+    details.insert("prefix".into(), address.prefixlen.to_string().into());
+    Value::Mapping(details)
+}
+
+fn interfaces(
+    links: &[&str],
+    addresses: &[&str],
+    routes4: &[&str],
+    routes6: &[&str],
+    facts: &mut Facts,
+) {
+    let mut devices: BTreeMap<String, Mapping> = BTreeMap::new();
+    for line in links {
+        let fields: Vec<&str> = line.split('|').collect();
+        let [name, macaddress, mtu, operstate, kind, special] = fields[..] else {
+            continue;
+        };
+        let kind = match (special, kind) {
+            ("", "772") => "loopback",
+            ("", "1") => "ether",
+            ("", _) => "unknown",
+            (special, _) => special,
+        };
+        devices.insert(
+            name.to_owned(),
+            Mapping::from_iter([
+                ("device".into(), Value::from(name)),
+                ("macaddress".into(), Value::from(macaddress)),
+                (
+                    "mtu".into(),
+                    mtu.parse::<u64>().map_or(Value::Null, Value::from),
+                ),
+                (
+                    "active".into(),
+                    Value::from(matches!(operstate, "up" | "unknown")),
+                ),
+                ("type".into(), Value::from(kind)),
+            ]),
+        );
+    }
+
+    let links: Vec<IpLink> = serde_json::from_str(&addresses.join("\n")).unwrap_or_default();
+    let mut all_ipv4 = Vec::new();
+    let mut all_ipv6 = Vec::new();
+    for link in &links {
+        let Some(device) = devices.get_mut(&link.ifname) else {
+            continue;
+        };
+        let mut ipv4: Vec<Value> = Vec::new();
+        let mut ipv6: Vec<Value> = Vec::new();
+        for address in &link.addr_info {
+            match address.family.as_str() {
+                "inet" => {
+                    if !address.local.starts_with("127.") {
+                        all_ipv4.push(Value::from(address.local.as_str()));
+                    }
+                    ipv4.push(ipv4_details(address));
+                }
+                "inet6" => {
+                    if address.local != "::1" {
+                        all_ipv6.push(Value::from(address.local.as_str()));
+                    }
+                    ipv6.push(Value::Mapping(Mapping::from_iter([
+                        ("address".into(), Value::from(address.local.as_str())),
+                        ("prefix".into(), Value::from(address.prefixlen.to_string())),
+                        (
+                            "scope".into(),
+                            Value::from(address.scope.as_deref().unwrap_or_default()),
+                        ),
+                    ])));
+                }
+                _ => {}
+            }
+        }
+        let mut ipv4 = ipv4.into_iter();
+        if let Some(primary) = ipv4.next() {
+            device.insert("ipv4".into(), primary);
+            let secondaries: Vec<Value> = ipv4.collect();
+            if !secondaries.is_empty() {
+                device.insert("ipv4_secondaries".into(), Value::Sequence(secondaries));
+            }
+        }
+        if !ipv6.is_empty() {
+            device.insert("ipv6".into(), Value::Sequence(ipv6));
+        }
+    }
+
+    // The default route's interface details, with the route's gateway
+    let default_route = |lines: &[&str], family: &str| {
+        let routes: Vec<IpRoute> = serde_json::from_str(&lines.join("\n")).unwrap_or_default();
+        let route = routes.into_iter().next()?;
+        let device = devices.get(&route.dev)?;
+        let mut default = Mapping::new();
+        match device.get(family) {
+            Some(Value::Mapping(address)) => default.extend(address.clone()),
+            Some(Value::Sequence(addresses)) => {
+                if let Some(Value::Mapping(address)) = addresses.first() {
+                    default.extend(address.clone());
+                }
+            }
+            _ => {}
+        }
+        default.insert("interface".into(), route.dev.as_str().into());
+        default.insert(
+            "gateway".into(),
+            route.gateway.map_or(Value::Null, Value::from),
+        );
+        for key in ["macaddress", "mtu", "type"] {
+            if let Some(value) = device.get(key) {
+                default.insert(key.into(), value.clone());
+            }
+        }
+        Some(Value::Mapping(default))
+    };
+    let default_ipv4 = default_route(routes4, "ipv4");
+    let default_ipv6 = default_route(routes6, "ipv6");
+
+    facts.insert(
+        "interfaces".into(),
+        Value::Sequence(
+            devices
+                .keys()
+                .map(|name| Value::from(name.as_str()))
+                .collect(),
+        ),
+    );
+    facts.insert(
+        "default_ipv4".into(),
+        default_ipv4.unwrap_or_else(|| Value::Mapping(Mapping::new())),
+    );
+    facts.insert(
+        "default_ipv6".into(),
+        default_ipv6.unwrap_or_else(|| Value::Mapping(Mapping::new())),
+    );
+    facts.insert("all_ipv4_addresses".into(), Value::Sequence(all_ipv4));
+    facts.insert("all_ipv6_addresses".into(), Value::Sequence(all_ipv6));
+    for (name, device) in devices {
+        facts.insert(name.replace(['-', ':', '.'], "_"), Value::Mapping(device));
+    }
+}
+
+fn virtualization(lines: &[&str], facts: &mut Facts) {
+    let detected = |index: usize| {
+        lines
+            .get(index)
+            .map(|line| line.trim())
+            .filter(|kind| !kind.is_empty() && *kind != "none")
+    };
+
+    let (kind, role) = match (detected(1), detected(0)) {
+        (Some(container), _) => (container, "guest"),
+        (None, Some(vm)) => (vm, "guest"),
+        (None, None) => ("none", "host"),
+    };
+    facts.insert("virtualization_type".into(), kind.into());
+    facts.insert("virtualization_role".into(), role.into());
+}
+
+#[async_trait]
+impl StructuredTask for SetupTask {
+    async fn run_structured(&self, context: TaskContext) -> TaskResult {
+        let entries = self
+            .gather_subset
+            .as_ref()
+            .map(GatherSubset::entries)
+            .unwrap_or_else(|| vec!["all".to_owned()]);
+        let sections = sections(&entries)?;
+
+        let mut script: Vec<&str> = sections
+            .iter()
+            .map(|section| self::script(*section))
+            .collect();
+        script.push("exit 0");
+        let script = script.join("\n");
+
+        let mut ctx = context.lock().await;
+        let executable = default_executable(&ctx)?;
+        let output = ctx
+            .run_command_opts(RunCommandOpts {
+                command: vec![executable.as_str(), "-c", &script],
+                capture: true,
+                read_only: true,
+                ..Default::default()
+            })
+            .wrap_err("failed to gather facts")?;
+
+        let parts = split_parts(&output.stdout);
+        let part = |name: &str| parts.get(name).map(Vec::as_slice).unwrap_or_default();
+
+        let mut facts = Facts::new();
+        for section in &sections {
+            match section {
+                Section::Platform => platform(part("platform"), &mut facts),
+                Section::Distribution => distribution(part("os_release"), &mut facts),
+                Section::User => user(part("user"), &mut facts),
+                Section::ServiceManager => service_manager(part("service_mgr"), &mut facts),
+                Section::Packages => packages(part("packages"), &mut facts),
+                Section::Cpu => cpu(part("nproc"), part("cpuinfo"), &mut facts),
+                Section::Memory => memory(part("meminfo"), &mut facts),
+                Section::Mounts => mounts(part("mounts"), part("df"), &mut facts),
+                Section::Interfaces => interfaces(
+                    part("interfaces"),
+                    part("addresses"),
+                    part("routes4"),
+                    part("routes6"),
+                    &mut facts,
+                ),
+                Section::Virtualization => virtualization(part("virt"), &mut facts),
+            }
+        }
+        facts.insert(
+            "gather_subset".into(),
+            Value::Sequence(entries.iter().map(|entry| entry.as_str().into()).collect()),
+        );
+
+        if let Some(filter) = &self.filter {
+            facts.retain(|name, _| {
+                filter.patterns().iter().any(|pattern| {
+                    render::wildcard_match(pattern, name)
+                        || render::wildcard_match(pattern, &format!("ansible_{name}"))
+                })
+            });
+        }
+        debug!(count = facts.len(), "gathered facts");
+
+        // Facts are host data and must never be evaluated as templates
+        let facts: Mapping = facts
+            .into_iter()
+            .map(|(name, value)| (Value::from(name), render::mark_unsafe(value)))
+            .collect();
+
+        for (name, value) in &facts {
+            if let Some(name) = name.as_str() {
+                ctx.facts.insert(format!("ansible_{name}"), value.clone());
+            }
+        }
+        let ansible_facts = match ctx.facts.remove("ansible_facts") {
+            Some(Value::Mapping(mut existing)) => {
+                existing.extend(facts.clone());
+                existing
+            }
+            _ => facts.clone(),
+        };
+        ctx.facts.insert(
+            "ansible_facts".into(),
+            Value::Mapping(ansible_facts.clone()),
+        );
+        ctx.fact_cache.update(
+            &ctx.inventory_hostname,
+            Mapping::from_iter([("ansible_facts".into(), Value::Mapping(ansible_facts))]),
+        )?;
+
+        Ok(TaskOutput::ok(Some(Value::Mapping(Mapping::from_iter([
+            ("ansible_facts".into(), Value::Mapping(facts)),
+        ])))))
+    }
+}
+
+inventory::submit! {
+    KeroseneTaskInfo::new_aliases("ansible.builtin.setup", &["setup"], &SetupTask::run)
+}
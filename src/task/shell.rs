@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use eyre::eyre;
 use serde::Deserialize;
 use serde_yaml::Value;
 use structstruck::strike;
@@ -18,39 +19,37 @@ strike! {
     }
 }
 
-fn default_executable(_ctx: &TaskContextInner) -> &'static str {
-    // TODO: use ctx for determining executable per OS etc.
-    "/bin/sh"
+/// The host's `ansible_shell_executable`, for targets whose POSIX shell is
+/// not `/bin/sh`.
+pub(crate) fn default_executable(ctx: &TaskContextInner) -> eyre::Result<String> {
+    match ctx.vars().get("ansible_shell_executable")? {
+        Some(Value::String(executable)) => Ok(executable),
+        Some(other) => Err(eyre!(
+            "ansible_shell_executable must be a string, got {other:?}"
+        )),
+        None => Ok("/bin/sh".to_owned()),
+    }
 }
 
 #[async_trait]
 impl StructuredTask for ShellTask {
     async fn run_structured(&self, context: TaskContext) -> TaskResult {
         let ctx = context.lock().await;
-        let executable = self
-            .executable
-            .as_deref()
-            .unwrap_or(default_executable(&ctx));
+        let executable = match &self.executable {
+            Some(executable) => executable.clone(),
+            None => default_executable(&ctx)?,
+        };
 
         let output = ctx.run_command_opts(RunCommandOpts {
-            command: vec![executable, "-c", self.cmd.as_str()],
+            command: vec![executable.as_str(), "-c", self.cmd.as_str()],
             working_directory: self.chdir.as_deref(),
             capture: true,
             ..Default::default()
         })?;
 
-        let mut result = serde_yaml::Mapping::new();
-        result.insert(
-            Value::String("stdout".into()),
-            Value::String(output.stdout.trim_end_matches('\n').into()),
-        );
-        result.insert(
-            Value::String("stderr".into()),
-            Value::String(output.stderr.trim_end_matches('\n').into()),
-        );
-        result.insert(Value::String("rc".into()), Value::Number(output.rc.into()));
-
-        Ok(TaskOutput::changed(Some(Value::Mapping(result))))
+        Ok(TaskOutput::changed(Some(Value::Mapping(
+            output.to_result(),
+        ))))
     }
 }
 
@@ -1,14 +1,21 @@
+use std::path::{Component, Path, PathBuf};
+
 use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use eyre::{Context, eyre};
+use minijinja::syntax::SyntaxConfig;
 use serde::Deserialize;
 
 use crate::{render, task::KeroseneTaskInfo};
 
 use super::{
-    RunCommandOpts, StdinSource, StructuredTask, TaskContext, TaskOutput, TaskResult,
+    RunCommandOpts, StdinSource, StructuredTask, TaskContext, TaskContextInner, TaskOutput,
+    TaskResult,
     copy::{build_install_command, resolve_local_file},
 };
 
+const DEFAULT_ANSIBLE_MANAGED: &str = "Ansible managed";
+
 #[derive(Debug, Deserialize)]
 pub struct TemplateTask {
     #[serde(default, rename = "src")]
@@ -24,55 +31,265 @@ pub struct TemplateTask {
     pub group: Option<String>,
     #[serde(default)]
     pub mode: Option<String>,
+    #[serde(default = "default_trim_blocks")]
+    pub trim_blocks: bool,
+    #[serde(default)]
+    pub lstrip_blocks: bool,
+    #[serde(default)]
+    pub newline_sequence: Option<String>,
+    #[serde(default)]
+    pub block_start_string: Option<String>,
+    #[serde(default)]
+    pub block_end_string: Option<String>,
+    #[serde(default)]
+    pub variable_start_string: Option<String>,
+    #[serde(default)]
+    pub variable_end_string: Option<String>,
+    #[serde(default)]
+    pub comment_start_string: Option<String>,
+    #[serde(default)]
+    pub comment_end_string: Option<String>,
+    #[serde(default)]
+    pub output_encoding: Option<String>,
+}
+
+fn default_trim_blocks() -> bool {
+    true
+}
+
+impl TemplateTask {
+    fn syntax(&self) -> eyre::Result<Option<SyntaxConfig>> {
+        let delimiters = [
+            &self.block_start_string,
+            &self.block_end_string,
+            &self.variable_start_string,
+            &self.variable_end_string,
+            &self.comment_start_string,
+            &self.comment_end_string,
+        ];
+        if delimiters.iter().all(|delimiter| delimiter.is_none()) {
+            return Ok(None);
+        }
+
+        let syntax = SyntaxConfig::builder()
+            .block_delimiters(
+                self.block_start_string
+                    .as_deref()
+                    .unwrap_or("{%")
+                    .to_owned(),
+                self.block_end_string.as_deref().unwrap_or("%}").to_owned(),
+            )
+            .variable_delimiters(
+                self.variable_start_string
+                    .as_deref()
+                    .unwrap_or("{{")
+                    .to_owned(),
+                self.variable_end_string
+                    .as_deref()
+                    .unwrap_or("}}")
+                    .to_owned(),
+            )
+            .comment_delimiters(
+                self.comment_start_string
+                    .as_deref()
+                    .unwrap_or("{#")
+                    .to_owned(),
+                self.comment_end_string
+                    .as_deref()
+                    .unwrap_or("#}")
+                    .to_owned(),
+            )
+            .build()
+            .wrap_err("invalid template delimiters")?;
+        Ok(Some(syntax))
+    }
+
+    fn newline_sequence(&self) -> eyre::Result<&str> {
+        match self.newline_sequence.as_deref() {
+            None => Ok("\n"),
+            Some(sequence @ ("\n" | "\r" | "\r\n")) => Ok(sequence),
+            Some(other) => Err(eyre!(
+                "invalid newline_sequence {other:?}, expected \"\\n\", \"\\r\" or \"\\r\\n\""
+            )),
+        }
+    }
+}
+
+/// Look up `name` in each of `directories`, refusing names that would
+/// escape them.
+fn load_template(directories: &[PathBuf], name: &str) -> Result<Option<String>, minijinja::Error> {
+    let relative = Path::new(name);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Ok(None);
+    }
+
+    for directory in directories {
+        let path = directory.join(relative);
+        if path.is_file() {
+            return std::fs::read_to_string(&path).map(Some).map_err(|err| {
+                minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("could not read template {}", path.display()),
+                )
+                .with_source(err)
+            });
+        }
+    }
+    Ok(None)
+}
+
+/// Encode the rendered template in the requested output encoding.
+fn encode(rendered: String, encoding: Option<&str>) -> eyre::Result<Vec<u8>> {
+    let encoding = encoding
+        .unwrap_or("utf-8")
+        .to_ascii_lowercase()
+        .replace('_', "-");
+    let narrow = |max: u32| {
+        rendered
+            .chars()
+            .map(|c| {
+                u8::try_from(u32::from(c))
+                    .ok()
+                    .filter(|&byte| u32::from(byte) <= max)
+                    .ok_or_else(|| eyre!("character {c:?} cannot be encoded as {encoding}"))
+            })
+            .collect::<eyre::Result<Vec<u8>>>()
+    };
+
+    match encoding.as_str() {
+        "utf-8" | "utf8" => Ok(rendered.into_bytes()),
+        "ascii" | "us-ascii" => narrow(0x7f),
+        "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" => narrow(0xff),
+        "utf-16-le" | "utf-16le" => {
+            Ok(rendered.encode_utf16().flat_map(u16::to_le_bytes).collect())
+        }
+        "utf-16-be" | "utf-16be" => {
+            Ok(rendered.encode_utf16().flat_map(u16::to_be_bytes).collect())
+        }
+        other => Err(eyre!("unsupported output_encoding '{other}'")),
+    }
+}
+
+/// Read a template source from the target. The file travels base64 encoded
+/// because captured output is decoded lossily, which would hide invalid
+/// UTF-8.
+fn read_remote_source(ctx: &TaskContextInner, file: &str) -> eyre::Result<String> {
+    let output = ctx
+        .run_command_opts(RunCommandOpts {
+            command: vec!["base64", "--", file],
+            capture: true,
+            read_only: true,
+            ..Default::default()
+        })
+        .wrap_err_with(|| format!("failed to read remote template source '{file}'"))?;
+
+    let encoded: String = output.stdout.split_whitespace().collect();
+    let bytes = BASE64
+        .decode(encoded)
+        .wrap_err_with(|| format!("failed to decode remote template source '{file}'"))?;
+    String::from_utf8(bytes)
+        .map_err(|_| eyre!("remote template source '{file}' is not valid UTF-8"))
+}
+
+fn controller_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim_end().to_owned())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_owned())
 }
 
 #[async_trait]
 impl StructuredTask for TemplateTask {
     async fn run_structured(&self, context: TaskContext) -> TaskResult {
-        let remote_src_file = match (&self.file, self.remote_src) {
-            (Some(file), true) => Some(file),
-            _ => None,
-        };
-
+        // The source is rendered here, so even a remote one is installed
+        // from stdin.
         let (command, _use_pipe) = build_install_command(
             &self.dest,
-            remote_src_file,
+            None,
             self.owner.as_ref(),
             self.group.as_ref(),
             self.mode.as_ref(),
         );
 
-        let mut environment = minijinja::Environment::new();
-        environment.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
-
         let ctx = context.lock().await;
         let (template_path, template_src) = if let Some(content) = &self.content {
-            (
-                "<inline>".to_string(),
-                String::from_utf8(content.as_bytes().into())?,
-            )
+            (None, String::from_utf8(content.as_bytes().into())?)
         } else if let Some(file) = &self.file {
             if !self.remote_src {
                 let file_path = resolve_local_file(&ctx, "templates", file).await?;
-                (
-                    file_path.to_string_lossy().to_string(),
-                    std::fs::read_to_string(file_path).wrap_err("failed to open local file")?,
-                )
+                let template_src =
+                    std::fs::read_to_string(&file_path).wrap_err("failed to open local file")?;
+                (Some(file_path), template_src)
             } else {
-                todo!("unsupported template task source: remote_src")
+                (Some(PathBuf::from(file)), read_remote_source(&ctx, file)?)
             }
         } else {
             return Err(eyre!("template task requires either 'src' or 'content'"));
         };
 
-        let resolved_vars = render::resolve_vars(&ctx.merged_vars())?;
-        let render_context = minijinja::Value::from_serialize(&resolved_vars);
-        let rendered =
-            environment.render_named_str(&template_path, &template_src, render_context)?;
+        // Includes, imports and extends are searched next to a local
+        // template, then in each role and play `templates/` directory.
+        let mut template_dirs: Vec<PathBuf> = template_path
+            .iter()
+            .filter(|_| !self.remote_src)
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        for directory in ctx.search_path() {
+            template_dirs.push(directory.join("templates"));
+            template_dirs.push(directory);
+        }
+
+        let mut environment = ctx.engine.environment();
+        environment.set_loader(move |name| load_template(&template_dirs, name));
+        environment.set_trim_blocks(self.trim_blocks);
+        environment.set_lstrip_blocks(self.lstrip_blocks);
+        environment.set_keep_trailing_newline(true);
+        if let Some(syntax) = self.syntax()? {
+            environment.set_syntax(syntax);
+        }
+
+        let mut template_vars = ctx.merged_vars();
+        template_vars
+            .entry("ansible_managed".to_owned())
+            .or_insert_with(|| DEFAULT_ANSIBLE_MANAGED.into());
+        template_vars.insert("template_host".to_owned(), controller_hostname().into());
+        template_vars.insert("template_destpath".to_owned(), self.dest.clone().into());
+        if let Some(path) = &template_path {
+            let fullpath = if self.remote_src {
+                path.clone()
+            } else {
+                std::path::absolute(path).unwrap_or_else(|_| path.clone())
+            };
+            template_vars.insert(
+                "template_path".to_owned(),
+                path.to_string_lossy().into_owned().into(),
+            );
+            template_vars.insert(
+                "template_fullpath".to_owned(),
+                fullpath.to_string_lossy().into_owned().into(),
+            );
+        }
+
+        let template_name = template_path.as_ref().map_or_else(
+            || "<inline>".to_owned(),
+            |path| path.to_string_lossy().into_owned(),
+        );
+        let vars = render::Vars::new(&ctx.engine, template_vars);
+        let mut rendered = vars.render_named(&environment, &template_name, &template_src)?;
+
+        let newline_sequence = self.newline_sequence()?;
+        if newline_sequence != "\n" {
+            rendered = rendered.replace('\n', newline_sequence);
+        }
+        let rendered = encode(rendered, self.output_encoding.as_deref())?;
 
         ctx.run_command_opts(RunCommandOpts {
             command,
-            stdin: Some(StdinSource::Bytes(rendered.into_bytes())),
+            stdin: Some(StdinSource::Bytes(rendered)),
             ..Default::default()
         })?;
 
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+use eyre::{Context, eyre};
+use kerosene::load_yaml;
+use serde_yaml::Value;
+use tracing::debug;
+
+use crate::{
+    render,
+    serde::play::{VarsFile, VarsPrompt},
+};
+
+/// Parse a single `-e/--extra-vars` argument.
+///
+/// Accepts `@path` (YAML or JSON file), an inline YAML/JSON mapping
+/// starting with `{`, or whitespace separated `key=value` pairs.
+pub fn parse_extra_vars(arg: &str) -> eyre::Result<HashMap<String, Value>> {
+    let arg = arg.trim();
+
+    if let Some(path) = arg.strip_prefix('@') {
+        return load_yaml(Path::new(path))?
+            .ok_or_else(|| eyre!("extra vars file '{path}' could not be opened"));
+    }
+
+    if arg.starts_with('[') {
+        return Err(eyre!("extra vars must be a mapping: '{arg}'"));
+    }
+    if arg.starts_with('{') {
+        return serde_yaml::from_str(arg)
+            .wrap_err_with(|| format!("extra vars '{arg}' are not a YAML/JSON mapping"));
+    }
+
+    let pairs = shlex::split(arg).ok_or_else(|| eyre!("failed to split extra vars '{arg}'"))?;
+    pairs
+        .into_iter()
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), Value::from(value))),
+            _ => Err(eyre!("extra var '{pair}' is not in key=value form")),
+        })
+        .collect()
+}
+
+/// Interactively ask for every `vars_prompt` entry not already defined in
+/// `defined`. Falls back to the prompt's default when stdin is not a terminal.
+pub fn prompt_vars(
+    prompts: &[VarsPrompt],
+    defined: &HashMap<String, Value>,
+) -> eyre::Result<HashMap<String, Value>> {
+    let mut vars = HashMap::new();
+
+    for prompt in prompts {
+        if defined.contains_key(&prompt.name) {
+            debug!(name = prompt.name, "vars_prompt already defined, skipping");
+            continue;
+        }
+
+        let default = prompt.default.as_ref().map(value_to_string);
+        if !std::io::stdin().is_terminal() {
+            let value = default.ok_or_else(|| {
+                eyre!(
+                    "cannot prompt for '{}' without a terminal and no default is set",
+                    prompt.name
+                )
+            })?;
+            vars.insert(prompt.name.clone(), Value::from(value));
+            continue;
+        }
+
+        let text = prompt.prompt.as_deref().unwrap_or(&prompt.name);
+        let text = match &default {
+            Some(default) if !prompt.private => format!("{text} [{default}]: "),
+            _ => format!("{text}: "),
+        };
+
+        let value = loop {
+            let value = read_input(&text, prompt.private)?;
+            if prompt.confirm {
+                let confirmation = read_input(&format!("confirm {text}"), prompt.private)?;
+                if value != confirmation {
+                    eprintln!("***** VALUES ENTERED DO NOT MATCH ****");
+                    continue;
+                }
+            }
+            break value;
+        };
+
+        let value = match default {
+            Some(default) if value.is_empty() => default,
+            _ => value,
+        };
+        vars.insert(prompt.name.clone(), Value::from(value));
+    }
+
+    Ok(vars)
+}
+
+fn read_input(text: &str, private: bool) -> eyre::Result<String> {
+    if private {
+        return rpassword::prompt_password(text).wrap_err("failed to read input");
+    }
+
+    let mut stderr = std::io::stderr();
+    stderr.write_all(text.as_bytes())?;
+    stderr.flush()?;
+
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .wrap_err("failed to read input")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_end().to_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Load play `vars_files`, in order. Paths are rendered with `vars` and
+/// resolved relative to the playbook directory; for a list of alternatives
+/// the first existing file is used.
+pub fn load_vars_files(
+    basedir: &Path,
+    vars_files: &[VarsFile],
+    vars: &render::Vars,
+) -> eyre::Result<HashMap<String, Value>> {
+    let mut loaded = HashMap::new();
+
+    for entry in vars_files {
+        let candidates = match entry {
+            VarsFile::Path(path) => std::slice::from_ref(path),
+            VarsFile::Alternatives(paths) => paths.as_slice(),
+        };
+
+        let mut found = false;
+        for candidate in candidates {
+            let path = basedir.join(vars.render_str(candidate)?);
+            if let Some(file_vars) = load_yaml::<HashMap<String, Value>>(&path)? {
+                debug!(?path, "loaded vars file");
+                loaded.extend(file_vars);
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            return Err(eyre!("vars file(s) {candidates:?} could not be found"));
+        }
+    }
+
+    Ok(loaded)
+}
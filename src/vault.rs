@@ -0,0 +1,279 @@
+//! Ansible Vault (`$ANSIBLE_VAULT;1.1;AES256` and `1.2` with vault id)
+//! encryption format.
+//!
+//! The payload is the hex encoding of `hex(salt) \n hex(hmac) \n hex(ciphertext)`.
+//! Keys are derived with PBKDF2-HMAC-SHA256 (10000 iterations) into an
+//! AES-256 key, an HMAC-SHA256 key and the initial CTR counter block.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use eyre::{Context, eyre};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+pub const HEADER: &str = "$ANSIBLE_VAULT";
+pub const DEFAULT_VAULT_ID: &str = "default";
+
+const CIPHER_NAME: &str = "AES256";
+const PBKDF2_ITERATIONS: u32 = 10000;
+const KEY_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+const BLOCK_SIZE: usize = 16;
+
+static SECRETS: OnceLock<Vec<VaultSecret>> = OnceLock::new();
+
+/// A vault password together with the vault id it was registered under.
+#[derive(Clone)]
+pub struct VaultSecret {
+    pub id: String,
+    password: Vec<u8>,
+}
+
+impl std::fmt::Debug for VaultSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultSecret")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VaultSecret {
+    pub fn new(id: impl Into<String>, password: impl Into<Vec<u8>>) -> Self {
+        Self {
+            id: id.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Load a secret from a password file. Executable files are run and
+    /// their standard output is used, like Ansible's vault password scripts.
+    pub fn from_file(id: impl Into<String>, path: &Path) -> eyre::Result<Self> {
+        let id = id.into();
+        let is_script = {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::metadata(path)
+                .wrap_err_with(|| format!("vault password file {path:?} could not be opened"))?
+                .permissions()
+                .mode()
+                & 0o111
+                != 0
+        };
+
+        let contents = if is_script {
+            let output = Command::new(path)
+                .arg("--vault-id")
+                .arg(&id)
+                .output()
+                .wrap_err_with(|| format!("failed to run vault password script {path:?}"))?;
+            if !output.status.success() {
+                return Err(eyre!(
+                    "vault password script {path:?} exited with {}",
+                    output.status
+                ));
+            }
+            output.stdout
+        } else {
+            std::fs::read(path)
+                .wrap_err_with(|| format!("failed to read vault password file {path:?}"))?
+        };
+
+        let password = contents.trim_ascii();
+        if password.is_empty() {
+            return Err(eyre!("vault password file {path:?} is empty"));
+        }
+
+        Ok(Self::new(id, password))
+    }
+
+    /// Parse a `--vault-id` value: `label@source` or just `source`, where
+    /// source is a password file or `prompt`.
+    pub fn from_vault_id(vault_id: &str) -> eyre::Result<Self> {
+        let (id, source) = match vault_id.split_once('@') {
+            Some((id, source)) => (id, source),
+            None => (DEFAULT_VAULT_ID, vault_id),
+        };
+
+        if source == "prompt" {
+            let prompt = if id == DEFAULT_VAULT_ID {
+                "Vault password: ".to_string()
+            } else {
+                format!("Vault password ({id}): ")
+            };
+            let password =
+                rpassword::prompt_password(prompt).wrap_err("failed to read vault password")?;
+            return Ok(Self::new(id, password));
+        }
+
+        Self::from_file(id, &PathBuf::from(source))
+    }
+}
+
+/// Register the vault secrets used to transparently decrypt vaulted files
+/// and `!vault` values. May only be called once.
+pub fn set_secrets(secrets: Vec<VaultSecret>) -> eyre::Result<()> {
+    SECRETS
+        .set(secrets)
+        .map_err(|_| eyre!("vault secrets were already configured"))
+}
+
+/// Vault secrets registered via [`set_secrets`].
+pub fn secrets() -> &'static [VaultSecret] {
+    SECRETS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Whether `data` starts with a vault envelope header.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.trim_ascii_start()
+        .starts_with(format!("{HEADER};").as_bytes())
+}
+
+/// Decrypt vaulted data using the registered secrets.
+pub fn decrypt(data: &[u8]) -> eyre::Result<Vec<u8>> {
+    decrypt_with(data, secrets()).map(|(plaintext, _)| plaintext)
+}
+
+/// Decrypt vaulted data, trying secrets whose id matches the envelope's
+/// vault id first. Returns the plaintext and the secret that opened it.
+pub fn decrypt_with<'a>(
+    data: &[u8],
+    secrets: &'a [VaultSecret],
+) -> eyre::Result<(Vec<u8>, &'a VaultSecret)> {
+    let envelope = std::str::from_utf8(data).wrap_err("vaulted data is not valid UTF-8")?;
+    let (header, body) = envelope
+        .trim()
+        .split_once('\n')
+        .ok_or_else(|| eyre!("vaulted data has no payload"))?;
+
+    let fields: Vec<&str> = header.trim().split(';').collect();
+    let vault_id = match fields.as_slice() {
+        [HEADER, "1.1", CIPHER_NAME] => None,
+        [HEADER, "1.2", CIPHER_NAME, vault_id] => Some(*vault_id),
+        _ => return Err(eyre!("unsupported vault format: {header}")),
+    };
+
+    if secrets.is_empty() {
+        return Err(eyre!(
+            "encountered vaulted data but no vault secrets were provided (use --vault-password-file or --vault-id)"
+        ));
+    }
+
+    let payload: String = body.split_whitespace().collect();
+    let payload = hex::decode(payload).wrap_err("vault payload is not valid hex")?;
+    let mut parts = payload.splitn(3, |byte| *byte == b'\n');
+    let (salt, hmac, ciphertext) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(salt), Some(hmac), Some(ciphertext)) => (
+            hex::decode(salt).wrap_err("vault salt is not valid hex")?,
+            hex::decode(hmac).wrap_err("vault HMAC is not valid hex")?,
+            hex::decode(ciphertext.trim_ascii()).wrap_err("vault ciphertext is not valid hex")?,
+        ),
+        _ => return Err(eyre!("vault payload is malformed")),
+    };
+
+    let (matching, others): (Vec<&VaultSecret>, Vec<&VaultSecret>) = secrets
+        .iter()
+        .partition(|secret| vault_id.is_some_and(|id| id == secret.id));
+
+    for secret in matching.into_iter().chain(others) {
+        let (key, hmac_key, iv) = derive_keys(&secret.password, &salt);
+
+        let mut mac = HmacSha256::new_from_slice(&hmac_key).expect("HMAC accepts any key length");
+        mac.update(&ciphertext);
+        if mac.verify_slice(&hmac).is_err() {
+            continue;
+        }
+
+        let mut plaintext = ciphertext.clone();
+        Aes256Ctr::new(&key.into(), &iv.into()).apply_keystream(&mut plaintext);
+        return Ok((unpad(plaintext)?, secret));
+    }
+
+    Err(eyre!(
+        "decryption failed: no vault secret matched{}",
+        vault_id
+            .map(|id| format!(" (vault id '{id}')"))
+            .unwrap_or_default()
+    ))
+}
+
+/// Encrypt `plaintext` with `secret`. The `1.2` format is used when the
+/// secret has a vault id other than `default`, `1.1` otherwise.
+pub fn encrypt(plaintext: &[u8], secret: &VaultSecret) -> eyre::Result<String> {
+    let mut salt = [0u8; 32];
+    getrandom::fill(&mut salt).map_err(|err| eyre!("failed to generate salt: {err}"))?;
+
+    let (key, hmac_key, iv) = derive_keys(&secret.password, &salt);
+
+    let padding = BLOCK_SIZE - plaintext.len() % BLOCK_SIZE;
+    let mut ciphertext = plaintext.to_vec();
+    ciphertext.extend(std::iter::repeat_n(padding as u8, padding));
+    Aes256Ctr::new(&key.into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+    let mut mac = HmacSha256::new_from_slice(&hmac_key).expect("HMAC accepts any key length");
+    mac.update(&ciphertext);
+    let hmac = mac.finalize().into_bytes();
+
+    let payload = hex::encode(
+        [
+            hex::encode(salt),
+            hex::encode(hmac),
+            hex::encode(ciphertext),
+        ]
+        .join("\n"),
+    );
+
+    let mut envelope = if secret.id == DEFAULT_VAULT_ID {
+        format!("{HEADER};1.1;{CIPHER_NAME}\n")
+    } else {
+        format!("{HEADER};1.2;{CIPHER_NAME};{}\n", secret.id)
+    };
+    for line in payload.as_bytes().chunks(80) {
+        envelope.push_str(std::str::from_utf8(line).expect("hex is ASCII"));
+        envelope.push('\n');
+    }
+
+    Ok(envelope)
+}
+
+fn derive_keys(
+    password: &[u8],
+    salt: &[u8],
+) -> ([u8; KEY_LENGTH], [u8; KEY_LENGTH], [u8; IV_LENGTH]) {
+    let mut derived = [0u8; 2 * KEY_LENGTH + IV_LENGTH];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, PBKDF2_ITERATIONS, &mut derived);
+
+    let mut key = [0u8; KEY_LENGTH];
+    let mut hmac_key = [0u8; KEY_LENGTH];
+    let mut iv = [0u8; IV_LENGTH];
+    key.copy_from_slice(&derived[..KEY_LENGTH]);
+    hmac_key.copy_from_slice(&derived[KEY_LENGTH..2 * KEY_LENGTH]);
+    iv.copy_from_slice(&derived[2 * KEY_LENGTH..]);
+    (key, hmac_key, iv)
+}
+
+fn unpad(mut data: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    let padding = usize::from(
+        *data
+            .last()
+            .ok_or_else(|| eyre!("vault plaintext is empty"))?,
+    );
+    if padding == 0
+        || padding > BLOCK_SIZE
+        || padding > data.len()
+        || !data[data.len() - padding..]
+            .iter()
+            .all(|byte| usize::from(*byte) == padding)
+    {
+        return Err(eyre!("vault plaintext has invalid padding"));
+    }
+
+    data.truncate(data.len() - padding);
+    Ok(data)
+}
@@ -0,0 +1,360 @@
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::{Args, Subcommand};
+use eyre::{Context, eyre};
+use kerosene::vault::{self, DEFAULT_VAULT_ID, VaultSecret};
+
+// Options selecting the vault secrets used to read vaulted data. Not a doc
+// comment, since clap would use it as the about text of flattening commands.
+#[derive(Debug, Args)]
+pub struct VaultOptions {
+    /// Vault password file (or executable script printing the password)
+    #[arg(long = "vault-password-file", env = "ANSIBLE_VAULT_PASSWORD_FILE")]
+    pub vault_password_files: Vec<PathBuf>,
+
+    /// Vault identity as label@source, where source is a password file or
+    /// `prompt`
+    #[arg(long = "vault-id")]
+    pub vault_ids: Vec<String>,
+
+    /// Ask for the vault password
+    #[arg(long = "ask-vault-pass", alias = "ask-vault-password", short = 'J')]
+    pub ask_vault_pass: bool,
+}
+
+impl VaultOptions {
+    pub fn secrets(&self) -> eyre::Result<Vec<VaultSecret>> {
+        let mut secrets = Vec::new();
+        if self.ask_vault_pass {
+            secrets.push(VaultSecret::from_vault_id("prompt")?);
+        }
+        for path in &self.vault_password_files {
+            secrets.push(VaultSecret::from_file(DEFAULT_VAULT_ID, path)?);
+        }
+        for vault_id in &self.vault_ids {
+            secrets.push(VaultSecret::from_vault_id(vault_id)?);
+        }
+        Ok(secrets)
+    }
+}
+
+/// Manage files and values encrypted with Ansible Vault
+#[derive(Debug, Subcommand)]
+pub enum VaultCommand {
+    /// Encrypt files in place, or stdin to stdout when no file is given
+    Encrypt {
+        #[command(flatten)]
+        vault: VaultOptions,
+        #[command(flatten)]
+        encrypt: EncryptOptions,
+        /// Write the result to this file instead (`-` for stdout)
+        #[arg(long)]
+        output: Option<PathBuf>,
+        files: Vec<PathBuf>,
+    },
+    /// Decrypt files in place, or stdin to stdout when no file is given
+    Decrypt {
+        #[command(flatten)]
+        vault: VaultOptions,
+        /// Write the result to this file instead (`-` for stdout)
+        #[arg(long)]
+        output: Option<PathBuf>,
+        files: Vec<PathBuf>,
+    },
+    /// Print the decrypted contents of files
+    View {
+        #[command(flatten)]
+        vault: VaultOptions,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Edit an encrypted file with $EDITOR, re-encrypting it afterwards
+    Edit {
+        #[command(flatten)]
+        vault: VaultOptions,
+        file: PathBuf,
+    },
+    /// Re-encrypt files with a new vault secret
+    Rekey {
+        #[command(flatten)]
+        vault: VaultOptions,
+        /// New vault password file
+        #[arg(long = "new-vault-password-file")]
+        new_vault_password_file: Option<PathBuf>,
+        /// New vault identity as label@source
+        #[arg(long = "new-vault-id")]
+        new_vault_id: Option<String>,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Encrypt a string and print it as a `!vault` YAML value
+    #[command(name = "encrypt_string", alias = "encrypt-string")]
+    EncryptString {
+        #[command(flatten)]
+        vault: VaultOptions,
+        #[command(flatten)]
+        encrypt: EncryptOptions,
+        /// Variable name to emit the value under
+        #[arg(long, short = 'n')]
+        name: Option<String>,
+        /// String to encrypt; read from stdin when omitted
+        value: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct EncryptOptions {
+    /// Vault id of the secret to encrypt with, when several are given
+    #[arg(long = "encrypt-vault-id")]
+    pub encrypt_vault_id: Option<String>,
+}
+
+impl VaultCommand {
+    pub fn run(self) -> eyre::Result<()> {
+        match self {
+            Self::Encrypt {
+                vault,
+                encrypt,
+                output,
+                files,
+            } => {
+                let secrets = vault.secrets()?;
+                let secret = encryption_secret(&secrets, &encrypt)?;
+                transform(&files, output.as_deref(), |path, data| {
+                    if vault::is_encrypted(&data) {
+                        return Err(eyre!("{} is already encrypted", display(path)));
+                    }
+                    Ok(vault::encrypt(&data, &secret)?.into_bytes())
+                })?;
+                eprintln!("Encryption successful");
+            }
+            Self::Decrypt {
+                vault,
+                output,
+                files,
+            } => {
+                let secrets = vault.secrets()?;
+                transform(&files, output.as_deref(), |path, data| {
+                    decrypt(path, &data, &secrets).map(|(plaintext, _)| plaintext)
+                })?;
+                eprintln!("Decryption successful");
+            }
+            Self::View { vault, files } => {
+                let secrets = vault.secrets()?;
+                let mut stdout = std::io::stdout().lock();
+                for file in files {
+                    let data = read_file(&file)?;
+                    let (plaintext, _) = decrypt(Some(&file), &data, &secrets)?;
+                    stdout.write_all(&plaintext)?;
+                }
+            }
+            Self::Edit { vault, file } => {
+                let secrets = vault.secrets()?;
+                edit(&file, &secrets)?;
+            }
+            Self::Rekey {
+                vault,
+                new_vault_password_file,
+                new_vault_id,
+                files,
+            } => {
+                let secrets = vault.secrets()?;
+                let new_secret = match (new_vault_password_file, new_vault_id) {
+                    (Some(path), None) => VaultSecret::from_file(DEFAULT_VAULT_ID, &path)?,
+                    (None, Some(vault_id)) => VaultSecret::from_vault_id(&vault_id)?,
+                    (None, None) => prompt_new_secret(DEFAULT_VAULT_ID)?,
+                    (Some(_), Some(_)) => {
+                        return Err(eyre!(
+                            "--new-vault-password-file and --new-vault-id are mutually exclusive"
+                        ));
+                    }
+                };
+
+                for file in files {
+                    let data = read_file(&file)?;
+                    let (plaintext, _) = decrypt(Some(&file), &data, &secrets)?;
+                    write_file(&file, vault::encrypt(&plaintext, &new_secret)?.as_bytes())?;
+                }
+                eprintln!("Rekey successful");
+            }
+            Self::EncryptString {
+                vault,
+                encrypt,
+                name,
+                value,
+            } => {
+                let secrets = vault.secrets()?;
+                let secret = encryption_secret(&secrets, &encrypt)?;
+                let value = match value {
+                    Some(value) => value.into_bytes(),
+                    None => {
+                        if std::io::stdin().is_terminal() {
+                            eprintln!("Reading plaintext input from stdin. (ctrl-d to end input)");
+                        }
+                        let mut value = Vec::new();
+                        std::io::stdin().read_to_end(&mut value)?;
+                        value
+                    }
+                };
+
+                let envelope = vault::encrypt(&value, &secret)?;
+                let mut snippet = match name {
+                    Some(name) => format!("{name}: !vault |\n"),
+                    None => "!vault |\n".to_string(),
+                };
+                for line in envelope.lines() {
+                    snippet.push_str("          ");
+                    snippet.push_str(line);
+                    snippet.push('\n');
+                }
+                print!("{snippet}");
+                eprintln!("Encryption successful");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Pick the secret to encrypt with: the one named by `--encrypt-vault-id`,
+/// the only one given, or a newly prompted password.
+fn encryption_secret(
+    secrets: &[VaultSecret],
+    options: &EncryptOptions,
+) -> eyre::Result<VaultSecret> {
+    if let Some(id) = &options.encrypt_vault_id {
+        return secrets
+            .iter()
+            .find(|secret| &secret.id == id)
+            .cloned()
+            .ok_or_else(|| eyre!("no vault secret was provided for vault id '{id}'"));
+    }
+
+    match secrets {
+        [] => prompt_new_secret(DEFAULT_VAULT_ID),
+        [secret] => Ok(secret.clone()),
+        _ => Err(eyre!(
+            "multiple vault secrets were provided, select one with --encrypt-vault-id"
+        )),
+    }
+}
+
+fn prompt_new_secret(id: &str) -> eyre::Result<VaultSecret> {
+    let password = rpassword::prompt_password("New Vault password: ")
+        .wrap_err("failed to read vault password")?;
+    let confirmation = rpassword::prompt_password("Confirm New Vault password: ")
+        .wrap_err("failed to read vault password")?;
+    if password != confirmation {
+        return Err(eyre!("passwords do not match"));
+    }
+    if password.is_empty() {
+        return Err(eyre!("vault password must not be empty"));
+    }
+    Ok(VaultSecret::new(id, password))
+}
+
+fn decrypt<'a>(
+    path: Option<&Path>,
+    data: &[u8],
+    secrets: &'a [VaultSecret],
+) -> eyre::Result<(Vec<u8>, &'a VaultSecret)> {
+    if !vault::is_encrypted(data) {
+        return Err(eyre!("{} is not vault encrypted", display(path)));
+    }
+    vault::decrypt_with(data, secrets)
+        .wrap_err_with(|| format!("failed to decrypt {}", display(path)))
+}
+
+/// Apply `f` to each file in place, or to stdin when no files are given.
+/// With `output`, the (single) result is written there instead.
+fn transform(
+    files: &[PathBuf],
+    output: Option<&Path>,
+    f: impl Fn(Option<&Path>, Vec<u8>) -> eyre::Result<Vec<u8>>,
+) -> eyre::Result<()> {
+    let stdin_only = files.is_empty() || (files.len() == 1 && files[0] == Path::new("-"));
+    if stdin_only {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        let result = f(None, data)?;
+        return write_output(output.unwrap_or(Path::new("-")), &result);
+    }
+
+    if output.is_some() && files.len() > 1 {
+        return Err(eyre!("--output can only be used with a single input file"));
+    }
+
+    for file in files {
+        let result = f(Some(file), read_file(file)?)?;
+        write_output(output.unwrap_or(file), &result)?;
+    }
+
+    Ok(())
+}
+
+fn edit(file: &Path, secrets: &[VaultSecret]) -> eyre::Result<()> {
+    let data = read_file(file)?;
+    let (plaintext, secret) = decrypt(Some(file), &data, secrets)?;
+
+    // Keep the extension so editors pick up syntax highlighting
+    let suffix = file
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut temp = tempfile::Builder::new()
+        .prefix("kerosene-vault-")
+        .suffix(&suffix)
+        .tempfile()
+        .wrap_err("failed to create temporary file")?;
+    temp.write_all(&plaintext)?;
+    temp.flush()?;
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut editor_command =
+        shlex::split(&editor).ok_or_else(|| eyre!("failed to parse $EDITOR '{editor}'"))?;
+    if editor_command.is_empty() {
+        return Err(eyre!("$EDITOR is empty"));
+    }
+    let program = editor_command.remove(0);
+    let status = Command::new(&program)
+        .args(editor_command)
+        .arg(temp.path())
+        .status()
+        .wrap_err_with(|| format!("failed to run editor '{program}'"))?;
+    if !status.success() {
+        return Err(eyre!("editor exited with {status}"));
+    }
+
+    let edited = std::fs::read(temp.path()).wrap_err("failed to read edited file")?;
+    if edited == plaintext {
+        eprintln!("File unchanged, not re-encrypting");
+        return Ok(());
+    }
+
+    write_file(file, vault::encrypt(&edited, secret)?.as_bytes())
+}
+
+fn read_file(path: &Path) -> eyre::Result<Vec<u8>> {
+    std::fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> eyre::Result<()> {
+    std::fs::write(path, data).wrap_err_with(|| format!("failed to write {}", path.display()))
+}
+
+fn write_output(path: &Path, data: &[u8]) -> eyre::Result<()> {
+    if path == Path::new("-") {
+        std::io::stdout().write_all(data)?;
+        return Ok(());
+    }
+    write_file(path, data)
+}
+
+fn display(path: Option<&Path>) -> String {
+    path.map(|path| path.display().to_string())
+        .unwrap_or_else(|| "stdin".to_string())
+}